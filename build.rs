@@ -0,0 +1,64 @@
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+
+/**
+Generate the plugin registry from the files found in src/plugins.
+Each file is a plugin module that defines a Settings type implementing
+PluginExecImplementation. The feature gate of a plugin is given by the
+#![cfg(...)] inner attribute at the top of its file, which is copied on
+its registration so that disabled plugins are left out.
+*/
+fn main() {
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let plugins_dir = Path::new(&manifest_dir).join("src").join("plugins");
+    println!("cargo:rerun-if-changed={}", plugins_dir.display());
+
+    let mut plugins: Vec<(String, String, Option<String>)> = vec![];
+    for entry in fs::read_dir(&plugins_dir).unwrap() {
+        let path = entry.unwrap().path();
+        let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+            Some(name) if path.extension().is_some_and(|ext| ext == "rs") && name != "mod" => {
+                name.to_owned()
+            }
+            _ => continue,
+        };
+
+        println!("cargo:rerun-if-changed={}", path.display());
+        let source = fs::read_to_string(&path).unwrap();
+        let gate = source
+            .lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("#![cfg("))
+            .map(|line| line.replacen("#![", "#[", 1));
+
+        plugins.push((name, path.display().to_string(), gate));
+    }
+    plugins.sort();
+
+    let mut code = String::new();
+    for (name, path, _) in &plugins {
+        let _ = writeln!(code, "#[path = {:?}]\npub mod {};\n", path, name);
+    }
+
+    let _ = writeln!(
+        code,
+        "/// All the plugins compiled in, sorted by name.\n#[allow(clippy::vec_init_then_push)]\npub fn registry() -> Vec<crate::plugins_list::Registration> {{"
+    );
+    let _ = writeln!(code, "    let mut registry = vec![];");
+    for (name, _, gate) in &plugins {
+        if let Some(gate) = gate {
+            let _ = writeln!(code, "    {}", gate);
+        }
+        let _ = writeln!(
+            code,
+            "    registry.push(crate::plugins_list::Registration::of::<{}::Settings>());",
+            name
+        );
+    }
+    let _ = writeln!(code, "    registry\n}}");
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    fs::write(Path::new(&out_dir).join("plugins.rs"), code).unwrap();
+}
@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: collectd-shrimp [options] [command] [config]
+
+commands:
+    run                 execute the plugin instances forever (default)
+    check               validate the configuration and report all the problems
+    once                execute each plugin instance a single time and exit
+    list-plugins        list the plugins compiled in
+    describe <plugin>   show the settings of a plugin
+    help                show this help
+
+options:
+    --hostname <name>       hostname of the values, instead of COLLECTD_HOSTNAME
+    --interval <seconds>    default interval, instead of COLLECTD_INTERVAL
+
+The configuration file defaults to /etc/collectd-shrimp.toml, or
+/usr/local/etc/collectd-shrimp.toml on FreeBSD.";
+
+/// What to do, see USAGE.
+pub enum Command {
+    Run,
+    Check,
+    Once,
+    ListPlugins,
+    Describe(String),
+    Help,
+}
+
+/// The parsed command line.
+pub struct Args {
+    pub command: Command,
+    pub config_path: Option<PathBuf>,
+    pub hostname: Option<String>,
+    pub interval: Option<String>,
+}
+
+/**
+Parse the command line arguments, without the name of the executable.
+A lone configuration path is still accepted as the run command, as well as the
+former --list-plugins flag.
+*/
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+    let mut command: Option<Command> = None;
+    let mut config_path: Option<PathBuf> = None;
+    let mut hostname: Option<String> = None;
+    let mut interval: Option<String> = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--hostname" => {
+                hostname = Some(args.next().ok_or("--hostname requires a value")?);
+            }
+            "--interval" => {
+                interval = Some(args.next().ok_or("--interval requires a value")?);
+            }
+            "-h" | "--help" => command = Some(Command::Help),
+            _ if arg.starts_with("--hostname=") => {
+                hostname = Some(arg["--hostname=".len()..].to_owned());
+            }
+            _ if arg.starts_with("--interval=") => {
+                interval = Some(arg["--interval=".len()..].to_owned());
+            }
+            _ if arg.starts_with('-') && arg != "--list-plugins" => {
+                return Err(format!("unknown option '{}'", arg));
+            }
+            _ if command.is_none() && config_path.is_none() => {
+                command = Some(match arg.as_str() {
+                    "run" => Command::Run,
+                    "check" => Command::Check,
+                    "once" => Command::Once,
+                    "list-plugins" | "--list-plugins" => Command::ListPlugins,
+                    "describe" => Command::Describe(
+                        args.next()
+                            .ok_or("describe requires the name of a plugin")?,
+                    ),
+                    "help" => Command::Help,
+                    _ => {
+                        config_path = Some(PathBuf::from(arg));
+                        Command::Run
+                    }
+                });
+            }
+            _ if config_path.is_none() => config_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    Ok(Args {
+        command: command.unwrap_or(Command::Run),
+        config_path,
+        hostname,
+        interval,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Args, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn runs_by_default() {
+        let args = parse_args(&[]).unwrap();
+        assert!(matches!(args.command, Command::Run));
+        assert_eq!(args.config_path, None);
+
+        let args = parse_args(&["/etc/shrimp.toml"]).unwrap();
+        assert!(matches!(args.command, Command::Run));
+        assert_eq!(args.config_path, Some(PathBuf::from("/etc/shrimp.toml")));
+    }
+
+    #[test]
+    fn parses_commands_and_options_in_any_order() {
+        let args = parse_args(&["--hostname", "h", "check", "c.toml", "--interval=5"]).unwrap();
+        assert!(matches!(args.command, Command::Check));
+        assert_eq!(args.config_path, Some(PathBuf::from("c.toml")));
+        assert_eq!(args.hostname.as_deref(), Some("h"));
+        assert_eq!(args.interval.as_deref(), Some("5"));
+
+        let args = parse_args(&["once", "--hostname=h"]).unwrap();
+        assert!(matches!(args.command, Command::Once));
+        assert_eq!(args.hostname.as_deref(), Some("h"));
+
+        let args = parse_args(&["describe", "file"]).unwrap();
+        assert!(matches!(args.command, Command::Describe(plugin) if plugin == "file"));
+
+        assert!(matches!(
+            parse_args(&["--list-plugins"]).unwrap().command,
+            Command::ListPlugins
+        ));
+        assert!(matches!(
+            parse_args(&["-h"]).unwrap().command,
+            Command::Help
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_arguments() {
+        assert!(parse_args(&["--hostname"]).is_err());
+        assert!(parse_args(&["--verbose"]).is_err());
+        assert!(parse_args(&["describe"]).is_err());
+        assert!(parse_args(&["run", "a.toml", "b.toml"]).is_err());
+    }
+}
@@ -1,45 +1,160 @@
+use serde::de::{self, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::error::Error;
-use std::path::PathBuf;
-use std::process::exit;
+use std::fmt;
+use std::path::{Path, PathBuf};
 
+use crate::interpolation;
+use crate::output::exec;
+#[cfg(feature = "graphite")]
+use crate::output::graphite;
+#[cfg(feature = "influxdb")]
+use crate::output::influxdb;
+#[cfg(feature = "json")]
+use crate::output::json;
+#[cfg(feature = "network")]
+use crate::output::network;
+#[cfg(feature = "prometheus")]
+use crate::output::prometheus;
 use crate::plugin;
-use crate::plugins;
-
-type Plugin<T> = Option<HashMap<String, PluginConfig<T>>>;
 
 /**
 Global configuration.
-Contains an optional plugin section for each plugin instance.
-A plugin section is a HashMap with instance name as key and
+Contains an optional plugin section for each plugin.
+A plugin section is a table with instance name as key and
 plugin settings as value.
+String values may reference environment variables and secret files,
+see the interpolation module.
 */
 #[derive(Debug, Deserialize)]
 pub struct Config {
-    pub null: Plugin<plugins::null::Settings>,
+    /**
+    Maximum number of plugin instances executed in parallel.
+    Defaults to 1, that is all instances are executed one after another.
+    */
+    pub concurrency: Option<usize>,
+
+    /**
+    Seconds given to the running plugin instances to complete on SIGTERM or
+    SIGINT, before exiting anyway. Defaults to 5.
+    */
+    pub shutdown_timeout: Option<f64>,
+
+    /**
+    Where to write the values.
+    Without this section, values are written as PUTVAL commands on stdout
+    for the collectd exec plugin.
+    */
+    pub output: Option<OutputConfig>,
+
+    /// Other files to take plugin sections from, such as drop-in fragments:
+    /// ```toml
+    /// include = ["/etc/collectd-shrimp.d/*.toml"]
+    /// ```
+    /// Patterns are relative to the directory of the configuration file and
+    /// matching files are read in alphabetical order. An instance cannot be
+    /// defined in more than one file.
+    pub include: Option<Vec<String>>,
+
+    /// Shared objects to load plugins from, see the dynamic module.
+    #[cfg(feature = "dynamic")]
+    pub libraries: Option<Vec<PathBuf>>,
+
+    /**
+    Keys inherited by every plugin instance, such as type or interval.
+    Settings differ from one plugin to another, so they are only inherited
+    from the defaults of a plugin:
+    ```toml
+    [defaults]
+    interval = 30
+
+    [http_latency.defaults]
+    type = "latency"
+    settings = { timeout = 2.0, user_agent = "probe" }
+
+    [http_latency.example]
+    target = "https://example.com"
+    ```
+    Tables are merged key by key, and the keys of an instance always win
+    over the inherited ones.
+    */
+    pub defaults: Option<toml::Table>,
+
+    /**
+    Plugin sections, by plugin name. The sections are parsed by the plugins
+    themselves when they are loaded, see plugins_list::Plugins::load().
+    The defaults have already been merged into the instances, and the
+    defaults table of each plugin removed.
+    */
+    #[serde(flatten)]
+    pub plugins: BTreeMap<String, toml::Value>,
+
+    /**
+    Where the plugin sections and instances are in the configuration file,
+    keyed by 'plugin' and 'plugin:instance'.
+    */
+    #[serde(skip)]
+    pub locations: HashMap<String, Location>,
+
+    /**
+    Sections only applied on restart as they are in the configuration file, see
+    RESTART_ONLY, so that a reload can tell whether they changed.
+    */
+    #[serde(skip)]
+    pub restart_only: toml::Table,
+
+    /**
+    Problems found while expanding, including and inheriting the configuration,
+    reported along with the ones of the plugin instances.
+    */
+    #[serde(skip)]
+    pub errors: Vec<String>,
+}
 
-    #[cfg(feature = "sysctl")]
-    pub sysctl: Plugin<plugins::sysctl::Settings>,
+/// Position of something in the configuration, shown in diagnostics.
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub path: PathBuf,
+    pub line: usize,
+}
 
-    #[cfg(feature = "sysctl_factor")]
-    pub sysctl_factor: Plugin<plugins::sysctl_factor::Settings>,
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.path.display(), self.line)
+    }
+}
 
-    #[cfg(all(target_os = "freebsd", feature = "sysctl_temp"))]
-    pub sysctl_temp: Plugin<plugins::sysctl_temp::Settings>,
+/**
+Output section of the configuration.
+Each output that is configured is enabled, so that the same
+values can be written to several outputs at the same time.
+*/
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputConfig {
+    /// PUTVAL commands on stdout for the collectd exec plugin.
+    pub exec: Option<exec::Settings>,
 
-    #[cfg(feature = "file")]
-    pub file: Plugin<plugins::file::Settings>,
+    /// Binary protocol over UDP to a collectd network plugin.
+    #[cfg(feature = "network")]
+    pub network: Option<network::Settings>,
 
-    #[cfg(feature = "file_factor")]
-    pub file_factor: Plugin<plugins::file_factor::Settings>,
+    /// Latest values served on /metrics for Prometheus to scrape.
+    #[cfg(feature = "prometheus")]
+    pub prometheus: Option<prometheus::Settings>,
 
-    #[cfg(feature = "http_latency")]
-    pub http_latency: Plugin<plugins::http_latency::Settings>,
+    /// Graphite plaintext lines to a carbon server over TCP or UDP.
+    #[cfg(feature = "graphite")]
+    pub graphite: Option<graphite::Settings>,
 
+    /// InfluxDB line protocol over HTTP or UDP.
+    #[cfg(feature = "influxdb")]
+    pub influxdb: Option<influxdb::Settings>,
 
-    #[cfg(feature = "telnet_latency")]
-    pub telnet_latency: Plugin<plugins::telnet_latency::Settings>,
+    /// One JSON object per line, to stdout or to a file.
+    #[cfg(feature = "json")]
+    pub json: Option<json::Settings>,
 }
 
 /**
@@ -49,6 +164,7 @@ It's up to the plugin to decide if it should fail
 or not if some optional key is missing.
 */
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct PluginConfig<T> {
     /**
     The type of data in the sense of collectd
@@ -67,25 +183,46 @@ pub struct PluginConfig<T> {
 
     /**
     Optionally change the interval for this plugin instance.
-    This must be greater than the interval provided by collectd.
-    Each time the plugin is executed, it checks if the last execution
-    is older than the custom configured interval and skip execution otherwise.
+    Otherwise the instance is executed at the interval provided by collectd.
+    The instance is scheduled on wall-clock multiples of its interval, which
+    may be shorter than or not a divisor of the collectd interval.
     */
-    pub interval: Option<f32>,
+    pub interval: Option<f64>,
+
+    /**
+    Optionally change what happens when a target cannot be measured.
+    By default the error is reported and the target is skipped.
+    */
+    pub on_error: Option<OnError>,
 
     /**
     The targets for the instance (if required by the plugin).
     If there is only one target, you could use "target" instead.
     If there is more than one target, if will be used as the "type" instance
-    in the collectd identifier.
+    in the collectd identifier, unless it is given a name, see TargetConfig.
     */
-    pub targets: Option<Vec<String>>,
+    pub targets: Option<Vec<TargetConfig<T>>>,
 
     /**
     The target for the instance, if there is only one.
     Note that you cannot specify both "targets" and "target" at the same time.
     */
-    pub target: Option<String>,
+    pub target: Option<TargetConfig<T>>,
+
+    /**
+    Optionally merge the results of consecutive targets into the data sources
+    of a single value, for types that have more than one data source.
+    For instance with "data_sources = 2" and the rx and tx byte counters of an
+    interface as targets, a single if_octets value is emitted as rx:tx.
+    The merged value uses the type instance of its first target.
+    */
+    pub data_sources: Option<usize>,
+
+    /**
+    Optionally check the values against thresholds and emit a notification
+    each time a value changes state between okay, warning and failure.
+    */
+    pub threshold: Option<ThresholdConfig>,
 
     /**
     The individual settings for the plugin.
@@ -94,64 +231,764 @@ pub struct PluginConfig<T> {
     pub settings: Option<T>,
 }
 
+/**
+A target, either given as is or as a table giving it a name, used as its type
+instance instead of the target itself, and settings of its own, e.g.:
+```toml
+targets = [
+    { name = "home", target = "https://example.com" },
+    { target = "https://example.com/slow", settings = { timeout = 10.0 } },
+    "https://example.org",
+]
+```
+*/
+#[derive(Debug, Clone, Deserialize)]
+#[serde(
+    untagged,
+    expecting = "expects a string or a { target, name, settings } table"
+)]
+pub enum TargetConfig<T> {
+    Target(String),
+    Table(TargetTable<T>),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TargetTable<T> {
+    pub target: String,
+    pub name: Option<String>,
+
+    /**
+    Settings of the target, only the ones that differ from the settings of
+    the instance are given since they are merged over them beforehand.
+    */
+    pub settings: Option<T>,
+}
+
+impl<T> TargetConfig<T> {
+    pub fn target(&self) -> &str {
+        match self {
+            TargetConfig::Target(target) => target,
+            TargetConfig::Table(table) => &table.target,
+        }
+    }
+
+    /// Same target without its own settings, see PluginConfig::map_settings().
+    fn without_settings<U>(self) -> TargetConfig<U> {
+        match self {
+            TargetConfig::Target(target) => TargetConfig::Target(target),
+            TargetConfig::Table(table) => TargetConfig::Table(TargetTable {
+                target: table.target,
+                name: table.name,
+                settings: None,
+            }),
+        }
+    }
+}
+
+/**
+What to do when a plugin fails to measure one of its targets.
+The error is always reported on stderr beforehand.
+*/
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OnError {
+    /// Do not emit any value for this target.
+    Skip,
+
+    /// Emit an undefined value (U), which collectd stores as NaN.
+    Unknown,
+
+    /// Emit the given value instead, e.g. `on_error = { sentinel = -1 }`.
+    Sentinel(f64),
+
+    /// Exit the program, like shrimp used to do.
+    Abort,
+}
+
+/**
+Thresholds of a plugin instance, e.g.:
+```toml
+[http_latency.web.threshold]
+warning_max = 0.5
+failure_max = 2.0
+persist = 3
+
+[http_latency.web.threshold.targets."https://example.com"]
+warning_max = 1.0
+```
+*/
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThresholdConfig {
+    /// Limits applied to every value of the instance.
+    #[serde(flatten)]
+    pub limits: Limits,
+
+    /// Keys that are not limits, since flattened structs cannot deny unknown fields.
+    #[serde(flatten)]
+    pub unknown: BTreeMap<String, toml::Value>,
+
+    /**
+    Limits overriding the ones above for some values, keyed by type instance
+    (that is the name or else the target, unless the plugin sets its own
+    type instance).
+    Only the limits that are given are overridden.
+    */
+    pub targets: Option<HashMap<String, Limits>>,
+}
+
+/// Bounds of the okay range of a value, all of them are optional.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub warning_min: Option<f64>,
+    pub warning_max: Option<f64>,
+    pub failure_min: Option<f64>,
+    pub failure_max: Option<f64>,
+
+    /**
+    Once a bound is crossed, the value must come back by this much
+    before the state changes again, so that a value oscillating around
+    a bound does not flood with notifications. 0 by default.
+    */
+    pub hysteresis: Option<f64>,
+
+    /**
+    Number of consecutive values in a new state before it is notified,
+    1 by default.
+    */
+    pub persist: Option<u32>,
+}
+
+impl Limits {
+    /// Limits of self, completed by the ones of other.
+    pub fn or(&self, other: &Limits) -> Limits {
+        Limits {
+            warning_min: self.warning_min.or(other.warning_min),
+            warning_max: self.warning_max.or(other.warning_max),
+            failure_min: self.failure_min.or(other.failure_min),
+            failure_max: self.failure_max.or(other.failure_max),
+            hysteresis: self.hysteresis.or(other.hysteresis),
+            persist: self.persist.or(other.persist),
+        }
+    }
+}
+
+impl<T> PluginConfig<T> {
+    /**
+    Same configuration with settings converted to another type.
+    The settings of the targets are dropped.
+    */
+    pub fn map_settings<U>(self, f: impl FnOnce(Option<T>) -> Option<U>) -> PluginConfig<U> {
+        PluginConfig {
+            r#type: self.r#type,
+            name: self.name,
+            interval: self.interval,
+            on_error: self.on_error,
+            targets: self.targets.map(|targets| {
+                targets
+                    .into_iter()
+                    .map(TargetConfig::without_settings)
+                    .collect()
+            }),
+            target: self.target.map(TargetConfig::without_settings),
+            data_sources: self.data_sources,
+            threshold: self.threshold,
+            settings: f(self.settings),
+        }
+    }
+
+    /// The targets as configured, in the order they are given to the plugin.
+    pub fn target_configs(&self) -> impl Iterator<Item = &TargetConfig<T>> {
+        self.targets.iter().flatten().chain(self.target.iter())
+    }
+
+    /// Name of each target if it has one, in the order they are given to the plugin.
+    pub fn target_names(&self) -> Vec<Option<&str>> {
+        self.target_configs()
+            .map(|target| match target {
+                TargetConfig::Table(table) => table.name.as_deref(),
+                TargetConfig::Target(_) => None,
+            })
+            .collect()
+    }
+
+    /// Whether some target has settings of its own.
+    pub fn has_target_settings(&self) -> bool {
+        self.target_configs()
+            .any(|target| matches!(target, TargetConfig::Table(t) if t.settings.is_some()))
+    }
+
+    /**
+    Settings of the target at the given index, in the order they are given to
+    the plugin: its own ones if it has some, which already include the settings
+    of the instance, or else the settings of the instance.
+    */
+    pub fn settings_of(&self, index: usize) -> Option<&T> {
+        let own = match self.target_configs().nth(index) {
+            Some(TargetConfig::Table(table)) => table.settings.as_ref(),
+            _ => None,
+        };
+
+        own.or(self.settings.as_ref())
+    }
+}
+
 impl<T> PluginConfig<T>
 where
     T: plugin::PluginExecImplementation,
 {
     /// Check if there is some settings configured.
-    #[allow(dead_code)]
-    pub fn check_setting_required(&self, instance: &str) {
+    pub fn check_setting_required(&self, instance: &str) -> Result<(), String> {
         if self.settings.is_none() {
-            eprintln!(
-                "warning: '{}:{}' plugin requires some setting(s)",
+            return Err(format!(
+                "'{}:{}' requires some settings",
                 T::name(),
                 instance
-            );
-            exit(1);
+            ));
         }
+
+        Ok(())
     }
 
     /// Check if there is no setting configured.
-    pub fn check_no_setting_required(&self, instance: &str) {
+    pub fn check_no_setting_required(&self, instance: &str) -> Result<(), String> {
         if self.settings.is_some() {
-            eprintln!(
-                "warning: '{}:{}' plugin requires no setting",
+            return Err(format!(
+                "'{}:{}' does not take any setting",
                 T::name(),
                 instance
-            );
-            exit(1);
+            ));
         }
+
+        Ok(())
     }
 
     /// Check if there are at least some target configured.
-    #[allow(dead_code)]
-    pub fn check_target_required(&self, instance: &str, targets: &[String]) {
+    pub fn check_target_required(&self, instance: &str, targets: &[String]) -> Result<(), String> {
         if targets.is_empty() {
-            eprintln!(
-                "warning: no target specified for '{}:{}' plugin",
+            return Err(format!(
+                "'{}:{}' requires at least one target",
                 T::name(),
                 instance
-            );
-            exit(1);
+            ));
         }
+
+        Ok(())
     }
 
     /// Check if there is no target configured.
-    pub fn check_no_target_required(&self, instance: &str, targets: &[String]) {
+    pub fn check_no_target_required(
+        &self,
+        instance: &str,
+        targets: &[String],
+    ) -> Result<(), String> {
         if !targets.is_empty() {
-            eprintln!(
-                "warning: '{}:{}' plugin requires no target",
+            return Err(format!(
+                "'{}:{}' does not take any target",
                 T::name(),
                 instance
-            );
-            exit(1);
+            ));
         }
+
+        Ok(())
     }
 }
 
+/// Message of a deserialization error on a single line, toml puts the keys on the next one.
+pub(crate) fn one_line(error: &toml::de::Error) -> String {
+    error.to_string().trim().replace('\n', " ")
+}
+
+/**
+Load the configuration file along with the files it includes.
+Only a file that cannot be read or parsed is an error, the other problems are
+collected in the errors of the configuration so that all of them are reported.
+*/
 pub fn config(path: &PathBuf) -> Result<Config, Box<dyn Error>> {
     let config_str = std::fs::read_to_string(path)?;
-    let config: Config = toml::from_str(&config_str)?;
+    let mut table: toml::Table = toml::from_str(&config_str)?;
+    let config_locations = locations(path, &config_str);
+    let errors: Vec<String> = interpolation::interpolate(&mut table)
+        .into_iter()
+        .map(|(key, e)| located(&config_locations, &key, e))
+        .collect();
+    let restart_only: toml::Table = table
+        .iter()
+        .filter(|(key, _)| RESTART_ONLY.contains(&key.as_str()))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+    let mut config: Config = toml::Value::Table(table)
+        .try_into()
+        .map_err(|e| one_line(&e))?;
+    config.locations = config_locations;
+    config.restart_only = restart_only;
+    config.errors = errors;
+
+    // the same file is only read once, even if it matches several patterns
+    let mut included = vec![path.canonicalize()?];
+    let directory = path.parent().unwrap_or(Path::new("."));
+    for pattern in config.include.take().unwrap_or_default() {
+        let pattern = directory.join(pattern);
+        let pattern = pattern.to_string_lossy();
+        let paths = match glob::glob(&pattern) {
+            Ok(paths) => paths,
+            Err(e) => {
+                let e = format!("invalid include pattern '{}': {}", pattern, e);
+                config.errors.push(located(&config.locations, "include", e));
+                continue;
+            }
+        };
+
+        for fragment_path in paths {
+            let fragment_path = match fragment_path {
+                Ok(fragment_path) => fragment_path,
+                Err(e) => {
+                    let e = format!("cannot include '{}': {}", e.path().display(), e.error());
+                    config.errors.push(located(&config.locations, "include", e));
+                    continue;
+                }
+            };
+            let canonical = match fragment_path.canonicalize() {
+                Ok(canonical) => canonical,
+                Err(e) => {
+                    let e = format!("cannot include '{}': {}", fragment_path.display(), e);
+                    config.errors.push(located(&config.locations, "include", e));
+                    continue;
+                }
+            };
+            if included.contains(&canonical) {
+                continue;
+            }
+
+            include(&mut config, &fragment_path);
+            included.push(canonical);
+        }
+    }
+
+    apply_defaults(&mut config);
 
     Ok(config)
 }
+
+/**
+Prefix a message with where the given key is in the configuration, if known.
+The key is either a plugin section or instance as 'plugin:instance', or a path
+of keys such as "output.influxdb.url" located at its instance or else its section.
+*/
+pub(crate) fn located(locations: &HashMap<String, Location>, key: &str, message: String) -> String {
+    let location = locations.get(key).or_else(|| {
+        let mut parts = key.splitn(3, '.');
+        let section = parts.next().unwrap_or_default();
+        parts
+            .next()
+            .and_then(|instance| locations.get(&format!("{}:{}", section, instance)))
+            .or_else(|| locations.get(section))
+    });
+
+    match location {
+        Some(location) => format!("{}: {}", location, message),
+        None => message,
+    }
+}
+
+/// Keys of the configuration that a reload does not apply, see scheduler::Scheduler.
+pub(crate) const RESTART_ONLY: &[&str] =
+    &["concurrency", "shutdown_timeout", "output", "libraries"];
+
+/// Keys of the configuration that only make sense in the main file.
+const MAIN_ONLY: &[&str] = &[
+    "concurrency",
+    "shutdown_timeout",
+    "output",
+    "include",
+    "libraries",
+    "defaults",
+];
+
+/// Add the plugin instances of an included file to the configuration.
+fn include(config: &mut Config, path: &Path) {
+    let fragment_str = match std::fs::read_to_string(path) {
+        Ok(fragment_str) => fragment_str,
+        Err(e) => {
+            let e = format!("cannot read included file '{}': {}", path.display(), e);
+            config.errors.push(e);
+            return;
+        }
+    };
+    let mut fragment: toml::Table = match toml::from_str(&fragment_str) {
+        Ok(fragment) => fragment,
+        Err(e) => {
+            let e = format!(
+                "cannot load included file '{}': {}",
+                path.display(),
+                one_line(&e)
+            );
+            config.errors.push(e);
+            return;
+        }
+    };
+    let fragment_locations = locations(path, &fragment_str);
+    for (key, e) in interpolation::interpolate(&mut fragment) {
+        config.errors.push(located(&fragment_locations, &key, e));
+    }
+
+    for (plugin_name, section) in fragment {
+        if MAIN_ONLY.contains(&plugin_name.as_str()) {
+            let e = format!(
+                "'{}' can only be given in the main configuration file",
+                plugin_name
+            );
+            config
+                .errors
+                .push(located(&fragment_locations, &plugin_name, e));
+            continue;
+        }
+
+        let fragment_instances = match section {
+            toml::Value::Table(instances) => instances,
+            _ => {
+                let e = format!("'{}' must be a table of instances", plugin_name);
+                config
+                    .errors
+                    .push(located(&fragment_locations, &plugin_name, e));
+                continue;
+            }
+        };
+
+        let instances = config
+            .plugins
+            .entry(plugin_name.to_owned())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        let instances = match instances {
+            toml::Value::Table(instances) => instances,
+            _ => continue, // reported when the plugins are loaded
+        };
+
+        if let Some(location) = fragment_locations.get(&plugin_name) {
+            config
+                .locations
+                .entry(plugin_name.to_owned())
+                .or_insert_with(|| location.to_owned());
+        }
+
+        for (instance_name, instance) in fragment_instances {
+            let name = format!("{}:{}", plugin_name, instance_name);
+
+            // the defaults table of a plugin is checked the same way as an instance
+            if instances.contains_key(&instance_name) {
+                let previous = match config.locations.get(&name) {
+                    Some(location) => location.to_string(),
+                    None => "another file".to_owned(),
+                };
+                let here = match fragment_locations.get(&name) {
+                    Some(location) => location.to_string(),
+                    None => path.display().to_string(),
+                };
+                config.errors.push(format!(
+                    "'{}' is defined in both {} and {}",
+                    name, previous, here
+                ));
+                continue;
+            }
+
+            if let Some(location) = fragment_locations.get(&name) {
+                config.locations.insert(name, location.to_owned());
+            }
+            instances.insert(instance_name, instance);
+        }
+    }
+}
+
+/// Merge the defaults of the configuration into each plugin instance.
+fn apply_defaults(config: &mut Config) {
+    let mut defaults = config.defaults.take().unwrap_or_default();
+    if defaults.remove("settings").is_some() {
+        let e = "settings can only be given in the defaults of a plugin".to_owned();
+        config
+            .errors
+            .push(located(&config.locations, "defaults.settings", e));
+    }
+
+    for (plugin_name, section) in config.plugins.iter_mut() {
+        // sections that are not tables are reported when the plugins are loaded
+        let instances = match section {
+            toml::Value::Table(instances) => instances,
+            _ => continue,
+        };
+
+        let mut plugin_defaults = match instances.remove("defaults") {
+            Some(toml::Value::Table(plugin_defaults)) => plugin_defaults,
+            Some(_) => {
+                let key = format!("{}.defaults", plugin_name);
+                let e = format!("'{}' must be a table", key);
+                config.errors.push(located(&config.locations, &key, e));
+                toml::Table::new()
+            }
+            None => toml::Table::new(),
+        };
+        merge(&mut plugin_defaults, &defaults);
+
+        for (_, instance) in instances.iter_mut() {
+            if let toml::Value::Table(instance) = instance {
+                merge(instance, &plugin_defaults);
+                merge_target_settings(instance);
+            }
+        }
+    }
+}
+
+/// Complete the settings of the targets given as tables with the ones of the instance.
+fn merge_target_settings(instance: &mut toml::Table) {
+    let settings = match instance.get("settings") {
+        Some(toml::Value::Table(settings)) => settings.to_owned(),
+        _ => return,
+    };
+
+    for key in ["targets", "target"] {
+        let targets = match instance.get_mut(key) {
+            Some(toml::Value::Array(targets)) => targets.iter_mut().collect(),
+            Some(target) => vec![target],
+            None => vec![],
+        };
+
+        for target in targets {
+            if let Some(toml::Value::Table(own)) = target.get_mut("settings") {
+                merge(own, &settings);
+            }
+        }
+    }
+}
+
+/// Add the keys of defaults that are missing from table, recursively.
+fn merge(table: &mut toml::Table, defaults: &toml::Table) {
+    for (key, default) in defaults {
+        match (table.get_mut(key), default) {
+            (Some(toml::Value::Table(value)), toml::Value::Table(default)) => merge(value, default),
+            (Some(_), _) => (),
+            (None, _) => {
+                table.insert(key.to_owned(), default.to_owned());
+            }
+        }
+    }
+}
+
+/**
+Find the line of each plugin section and instance out of the position of their
+keys, which unlike tables always have one. This is only used in diagnostics, so
+nothing is located if the keys cannot be parsed this way.
+*/
+fn locations(path: &Path, config_str: &str) -> HashMap<String, Location> {
+    let mut locations = HashMap::new();
+    let sections: BTreeMap<toml::Spanned<String>, Keys> = match toml::from_str(config_str) {
+        Ok(sections) => sections,
+        Err(_) => return locations,
+    };
+
+    let location = |key: &toml::Spanned<String>| Location {
+        path: path.to_owned(),
+        line: config_str[..key.span().start].matches('\n').count() + 1,
+    };
+
+    for (section, instances) in &sections {
+        locations.insert(section.get_ref().to_owned(), location(section));
+        for instance in &instances.0 {
+            let name = format!("{}:{}", section.get_ref(), instance.get_ref());
+            locations.insert(name, location(instance));
+        }
+    }
+
+    locations
+}
+
+/// Keys of a table along with their position, nothing for other values.
+struct Keys(Vec<toml::Spanned<String>>);
+
+impl<'de> Deserialize<'de> for Keys {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(KeysVisitor)
+    }
+}
+
+struct KeysVisitor;
+
+impl<'de> Visitor<'de> for KeysVisitor {
+    type Value = Keys;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "any value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Keys, A::Error> {
+        let mut keys = vec![];
+        while let Some(key) = map.next_key()? {
+            map.next_value::<IgnoredAny>()?;
+            keys.push(key);
+        }
+
+        Ok(Keys(keys))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Keys, A::Error> {
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(Keys(vec![]))
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<Keys, E> {
+        Ok(Keys(vec![]))
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<Keys, E> {
+        Ok(Keys(vec![]))
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<Keys, E> {
+        Ok(Keys(vec![]))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<Keys, E> {
+        Ok(Keys(vec![]))
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<Keys, E> {
+        Ok(Keys(vec![]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(toml: &str) -> toml::Table {
+        toml::from_str(toml).unwrap()
+    }
+
+    #[test]
+    fn merge_keeps_the_keys_of_the_table() {
+        let mut instance = table(
+            r#"
+            type = "gauge"
+            settings = { timeout = 1.0 }
+            "#,
+        );
+        merge(
+            &mut instance,
+            &table(
+                r#"
+                type = "latency"
+                interval = 30
+                settings = { timeout = 2.0, user_agent = "probe" }
+                "#,
+            ),
+        );
+
+        assert_eq!(
+            instance,
+            table(
+                r#"
+                type = "gauge"
+                interval = 30
+                settings = { timeout = 1.0, user_agent = "probe" }
+                "#
+            )
+        );
+    }
+
+    #[test]
+    fn defaults_are_inherited_by_each_instance() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [defaults]
+            interval = 30
+            type = "gauge"
+
+            [http_latency.defaults]
+            type = "latency"
+            settings = { timeout = 2.0, user_agent = "probe" }
+
+            [http_latency.a]
+            target = "https://example.com"
+
+            [http_latency.b]
+            interval = 10
+            settings = { timeout = 1.0 }
+            targets = [{ target = "https://example.org", settings = { user_agent = "other" } }]
+            "#,
+        )
+        .unwrap();
+        apply_defaults(&mut config);
+
+        assert!(config.errors.is_empty());
+        assert_eq!(
+            config.plugins["http_latency"],
+            toml::Value::Table(table(
+                r#"
+                [a]
+                target = "https://example.com"
+                interval = 30
+                type = "latency"
+                settings = { timeout = 2.0, user_agent = "probe" }
+
+                [b]
+                interval = 10
+                type = "latency"
+                settings = { timeout = 1.0, user_agent = "probe" }
+                targets = [{ target = "https://example.org", settings = { timeout = 1.0, user_agent = "other" } }]
+                "#
+            ))
+        );
+    }
+
+    #[test]
+    fn settings_are_not_inherited_from_the_global_defaults() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [defaults]
+            settings = { timeout = 2.0 }
+
+            [http_latency.a]
+            target = "https://example.com"
+            "#,
+        )
+        .unwrap();
+        apply_defaults(&mut config);
+
+        assert_eq!(config.errors.len(), 1);
+        assert!(config.plugins["http_latency"]["a"]
+            .get("settings")
+            .is_none());
+    }
+
+    #[test]
+    fn instances_defined_twice_name_both_files() {
+        let dir = std::env::temp_dir().join(format!("shrimp-include-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("conf.d")).unwrap();
+        let main = dir.join("main.toml");
+        let fragment = dir.join("conf.d").join("a.toml");
+        std::fs::write(
+            &main,
+            "include = [\"conf.d/*.toml\"]\n\n[file.a]\ntarget = \"/x\"\n",
+        )
+        .unwrap();
+        std::fs::write(
+            &fragment,
+            "[file.b]\ntarget = \"/y\"\n\n[file.a]\ntarget = \"/z\"\n",
+        )
+        .unwrap();
+
+        let config = config(&main);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(
+            config.errors,
+            [format!(
+                "'file:a' is defined in both {}:3 and {}:4",
+                main.display(),
+                fragment.display()
+            )]
+        );
+        assert_eq!(config.plugins["file"]["a"]["target"].as_str(), Some("/x"));
+        assert_eq!(config.plugins["file"]["b"]["target"].as_str(), Some("/y"));
+    }
+}
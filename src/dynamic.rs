@@ -0,0 +1,407 @@
+/*!
+Plugins loaded at runtime from shared objects listed in the configuration:
+```toml
+libraries = ["/usr/local/lib/shrimp/libprobe.so"]
+
+[probe.instance]
+type = "gauge"
+target = "..."
+```
+A library exports a function named shrimp_plugin, taking no argument and
+returning a pointer to a static ShrimpPlugin that describes the plugin with
+C calling convention. Strings are NUL terminated UTF-8. Each loaded plugin
+gets its own section named after it, like the built-in ones.
+*/
+
+use libloading::Library;
+use std::error::Error;
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::path::Path;
+use std::ptr;
+use std::time;
+
+use crate::config::{self, PluginConfig};
+use crate::plugin::{self, PluginError, PluginExecImplementation, PluginResult, State};
+use crate::plugins_list::Registration;
+
+/// Version of the interface below, bumped on each incompatible change.
+pub const ABI_VERSION: u32 = 1;
+
+/// Name of the function exported by the libraries.
+pub const ENTRY_POINT: &str = "shrimp_plugin";
+
+/// Result for one target/type-instance, see plugin::PluginResult.
+#[repr(C)]
+pub struct ShrimpResult {
+    /// Time of measurement in seconds since the epoch, 0 for now.
+    pub time: f64,
+
+    /// One value for each data source of the type.
+    pub values: *const *const c_char,
+    pub values_len: usize,
+
+    /// Index of the target used to compute the result, or -1.
+    pub target: isize,
+
+    /// Type instance to use instead of the target, or NULL.
+    pub type_instance: *const c_char,
+
+    /// If not NULL, the target could not be measured and this is why. Values are then ignored.
+    pub error: *const c_char,
+}
+
+/// Callback handed over to exec, called once for each result.
+pub type EmitFn = unsafe extern "C" fn(context: *mut c_void, result: *const ShrimpResult);
+
+/**
+Description of a plugin, mirroring plugin::PluginExecImplementation.
+Settings are handed over as the TOML serialization of the settings table of
+the instance, or NULL without settings.
+*/
+#[repr(C)]
+pub struct ShrimpPlugin {
+    /// Must be ABI_VERSION.
+    pub abi_version: u32,
+
+    /// Name of the plugin, as used in the collectd identifier and the configuration.
+    pub name: *const c_char,
+
+    pub desc: *const c_char,
+
+    /**
+    Check the configuration of an instance at startup.
+    Return NULL if it is valid, otherwise an error message.
+    */
+    pub pre: unsafe extern "C" fn(
+        instance: *const c_char,
+        settings: *const c_char,
+        targets: *const *const c_char,
+        targets_len: usize,
+    ) -> *const c_char,
+
+    /// Create the state of an instance, which may be NULL.
+    pub state_new: unsafe extern "C" fn(
+        instance: *const c_char,
+        settings: *const c_char,
+        targets: *const *const c_char,
+        targets_len: usize,
+    ) -> *mut c_void,
+
+    /// Free the state of an instance, also called on graceful shutdown.
+    pub state_free: unsafe extern "C" fn(state: *mut c_void),
+
+    /**
+    Execute an instance and call emit with the given context for each result.
+    The results only have to be valid during the call to emit. Instances of the
+    plugin may be executed concurrently from different threads, but a given
+    state is only used by a single thread at a time.
+    */
+    pub exec: unsafe extern "C" fn(
+        instance: *const c_char,
+        settings: *const c_char,
+        state: *mut c_void,
+        targets: *const *const c_char,
+        targets_len: usize,
+        emit: EmitFn,
+        context: *mut c_void,
+    ),
+}
+
+/// A loaded plugin, libraries are never unloaded.
+pub struct External {
+    plugin: &'static ShrimpPlugin,
+    name: &'static str,
+    desc: &'static str,
+}
+
+// the plugin description is immutable and the interface requires exec to be thread safe
+unsafe impl Send for External {}
+unsafe impl Sync for External {}
+
+/// Settings of an instance of an external plugin.
+#[derive(Clone)]
+pub struct Settings {
+    external: &'static External,
+    settings: Option<CString>,
+}
+
+impl Settings {
+    fn settings_ptr(&self) -> *const c_char {
+        self.settings
+            .as_ref()
+            .map_or(ptr::null(), |settings| settings.as_ptr())
+    }
+}
+
+/// State of an instance of an external plugin, along with the strings handed over to it.
+pub struct ExternalState {
+    external: &'static External,
+    state: *mut c_void,
+    instance: CString,
+    targets: Vec<CString>,
+}
+
+// a state is only used by a single thread at a time
+unsafe impl Send for ExternalState {}
+
+impl Drop for ExternalState {
+    fn drop(&mut self) {
+        unsafe { (self.external.plugin.state_free)(self.state) };
+    }
+}
+
+impl State<Settings> for ExternalState {
+    fn new(instance: &str, conf: &PluginConfig<Settings>, targets: &[String]) -> Self {
+        let settings = conf.settings.as_ref().unwrap();
+        // checked by pre()
+        let instance_str = c_string(instance).unwrap();
+        let targets = c_strings(targets).unwrap();
+        let target_ptrs: Vec<*const c_char> =
+            targets.iter().map(|target| target.as_ptr()).collect();
+
+        let state = unsafe {
+            (settings.external.plugin.state_new)(
+                instance_str.as_ptr(),
+                settings.settings_ptr(),
+                target_ptrs.as_ptr(),
+                target_ptrs.len(),
+            )
+        };
+
+        Self {
+            external: settings.external,
+            state,
+            instance: instance_str,
+            targets,
+        }
+    }
+}
+
+impl PluginExecImplementation for Settings {
+    type PluginState = ExternalState;
+
+    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) -> Result<(), String> {
+        let settings = conf.settings.as_ref().unwrap();
+        let invalid = |e: String| format!("'{}:{}' {}", settings.external.name, instance, e);
+        let instance_str = c_string(instance).map_err(invalid)?;
+        let targets = c_strings(targets).map_err(invalid)?;
+        let target_ptrs: Vec<*const c_char> =
+            targets.iter().map(|target| target.as_ptr()).collect();
+
+        let error = unsafe {
+            (settings.external.plugin.pre)(
+                instance_str.as_ptr(),
+                settings.settings_ptr(),
+                target_ptrs.as_ptr(),
+                target_ptrs.len(),
+            )
+        };
+
+        match unsafe { string(error) } {
+            Some(error) => Err(invalid(error)),
+            None => Ok(()),
+        }
+    }
+
+    fn exec<'a>(
+        _instance: &str,
+        conf: &PluginConfig<Self>,
+        state: &mut Self::PluginState,
+        targets: &'a [String],
+    ) -> Vec<Result<PluginResult<'a>, PluginError<'a>>> {
+        let settings = conf.settings.as_ref().unwrap();
+        let target_ptrs: Vec<*const c_char> =
+            state.targets.iter().map(|target| target.as_ptr()).collect();
+        let mut context = Context { targets, results: vec![] };
+
+        unsafe {
+            (settings.external.plugin.exec)(
+                state.instance.as_ptr(),
+                settings.settings_ptr(),
+                state.state,
+                target_ptrs.as_ptr(),
+                target_ptrs.len(),
+                emit,
+                &mut context as *mut Context as *mut c_void,
+            )
+        };
+
+        context.results
+    }
+
+    fn name() -> &'static str {
+        "external"
+    }
+
+    fn desc() -> &'static str {
+        "Plugin loaded from a shared object."
+    }
+
+    fn name_of(conf: &PluginConfig<Self>) -> &'static str {
+        conf.settings
+            .as_ref()
+            .map_or(Self::name(), |settings| settings.external.name)
+    }
+
+    fn desc_of(conf: &PluginConfig<Self>) -> &'static str {
+        conf.settings
+            .as_ref()
+            .map_or(Self::desc(), |settings| settings.external.desc)
+    }
+}
+
+/// Results collected by emit during the execution of an instance.
+struct Context<'a> {
+    targets: &'a [String],
+    results: Vec<Result<PluginResult<'a>, PluginError<'a>>>,
+}
+
+unsafe extern "C" fn emit(context: *mut c_void, result: *const ShrimpResult) {
+    let context = &mut *(context as *mut Context);
+    let result = match result.as_ref() {
+        Some(result) => result,
+        None => return,
+    };
+
+    let target = usize::try_from(result.target)
+        .ok()
+        .and_then(|index| context.targets.get(index))
+        .map(String::as_str);
+    let type_instance = string(result.type_instance);
+
+    if let Some(message) = string(result.error) {
+        context
+            .results
+            .push(Err(PluginError { target, type_instance, message }));
+        return;
+    }
+
+    let values = if result.values.is_null() {
+        vec![]
+    } else {
+        (0..result.values_len)
+            .map(|index| string(*result.values.add(index)).unwrap_or("U".to_owned()))
+            .collect()
+    };
+
+    // a panic cannot unwind out of this function, it would abort
+    let time = if result.time > 0.0 {
+        match time::Duration::try_from_secs_f64(result.time) {
+            Ok(time) => time,
+            Err(_) => {
+                let message = format!("invalid time {:e}", result.time);
+                context
+                    .results
+                    .push(Err(PluginError { target, type_instance, message }));
+                return;
+            }
+        }
+    } else {
+        plugin::now()
+    };
+
+    context
+        .results
+        .push(Ok(PluginResult { time, values, target, type_instance }));
+}
+
+/// Copy a string from the plugin, NULL becomes None.
+unsafe fn string(ptr: *const c_char) -> Option<String> {
+    if ptr.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(ptr).to_string_lossy().into_owned())
+    }
+}
+
+/// Convert a string for the plugin, which fails if it contains a NUL byte.
+fn c_string(value: &str) -> Result<CString, String> {
+    CString::new(value).map_err(|_| {
+        format!(
+            "cannot hand over '{}' to the plugin, it contains a NUL byte",
+            value.escape_debug()
+        )
+    })
+}
+
+fn c_strings(values: &[String]) -> Result<Vec<CString>, String> {
+    values.iter().map(|value| c_string(value)).collect()
+}
+
+/// Load a plugin from a shared object and register it.
+pub fn load(path: &Path) -> Result<Registration, Box<dyn Error>> {
+    let library: &'static Library = Box::leak(Box::new(unsafe { Library::new(path)? }));
+
+    let entry = unsafe {
+        library.get::<unsafe extern "C" fn() -> *const ShrimpPlugin>(ENTRY_POINT.as_bytes())?
+    };
+    let plugin = unsafe { entry().as_ref() }.ok_or("the plugin description is NULL")?;
+
+    if plugin.abi_version != ABI_VERSION {
+        return Err(format!(
+            "the plugin is built for version {} of the interface, expected {}",
+            plugin.abi_version, ABI_VERSION
+        )
+        .into());
+    }
+
+    let name = unsafe { static_str(plugin.name) }.ok_or("the plugin has no valid name")?;
+    let desc = unsafe { static_str(plugin.desc) }.unwrap_or("");
+    let external: &'static External = Box::leak(Box::new(External { plugin, name, desc }));
+
+    // the interface does not describe the settings
+    Ok(Registration::new(
+        name,
+        desc,
+        &[],
+        Box::new(move |instance_name, config, hostname, interval_duration| {
+            instance(external, instance_name, config, hostname, interval_duration)
+        }),
+    ))
+}
+
+/// Borrow a string of the plugin, which lives as long as the library.
+unsafe fn static_str(ptr: *const c_char) -> Option<&'static str> {
+    if ptr.is_null() {
+        None
+    } else {
+        CStr::from_ptr(ptr).to_str().ok()
+    }
+}
+
+/// Parse the configuration of an instance of an external plugin and create it.
+fn instance(
+    external: &'static External,
+    instance: &str,
+    config: toml::Value,
+    hostname: &str,
+    interval_duration: time::Duration,
+) -> Result<Box<dyn plugin::ExecutablePlugin>, Box<dyn Error>> {
+    let config: PluginConfig<toml::Value> = config
+        .try_into()
+        .map_err(|e| format!("'{}:{}' {}", external.name, instance, config::one_line(&e)))?;
+
+    // the settings are handed over to the external plugin once for the whole instance
+    if config.has_target_settings() {
+        return Err(format!(
+            "'{}:{}' targets cannot have settings of their own",
+            external.name, instance
+        )
+        .into());
+    }
+
+    let settings = match &config.settings {
+        Some(settings) => Some(CString::new(toml::to_string(settings)?)?),
+        None => None,
+    };
+    let config = config.map_settings(|_| Some(Settings { external, settings }));
+
+    let plugin_instance: plugin::PluginInstance<Settings> = plugin::PluginInstance::new(
+        config,
+        hostname.to_owned(),
+        instance.to_owned(),
+        interval_duration,
+    )?;
+
+    Ok(Box::new(plugin_instance))
+}
@@ -0,0 +1,197 @@
+/*!
+Expansion of the string values of the configuration:
+* `${VAR}` is replaced by the value of the environment variable VAR.
+* `${VAR:-default}` is replaced by the default if VAR is not set or empty.
+* `${file:/run/secrets/token}` is replaced by the content of the file, without
+  its trailing newline. The content is considered a secret and is redacted
+  from the diagnostics, see redact().
+* `$${` is a literal `${`.
+*/
+
+use std::env;
+use std::fs;
+use std::sync::Mutex;
+
+/// Secrets read so far, longest first. They are kept across reloads since
+/// instances created from a previous configuration may still use them.
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Shown instead of the secrets.
+const REDACTED: &str = "***";
+
+/**
+Expand the string values of a table, recursively. Every value is expanded even
+if some cannot be, which are returned as their key path along with the error.
+*/
+pub fn interpolate(table: &mut toml::Table) -> Vec<(String, String)> {
+    let mut errors = vec![];
+    for (key, value) in table.iter_mut() {
+        interpolate_value(key, value, &mut errors);
+    }
+
+    errors
+}
+
+/// Expand a value, whose key path is used in errors.
+fn interpolate_value(path: &str, value: &mut toml::Value, errors: &mut Vec<(String, String)>) {
+    match value {
+        toml::Value::String(string) => match expand(string) {
+            Ok(expanded) => *string = expanded,
+            Err(e) => errors.push((path.to_owned(), format!("'{}' {}", path, e))),
+        },
+        toml::Value::Array(values) => {
+            for value in values.iter_mut() {
+                interpolate_value(path, value, errors);
+            }
+        }
+        toml::Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                interpolate_value(&format!("{}.{}", path, key), value, errors);
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Expand the references of a string.
+fn expand(string: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(string.len());
+    let mut rest = string;
+
+    while let Some(start) = rest.find("${") {
+        // an escaped reference is kept as is, without its first $
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or("has an unterminated ${ reference")?;
+        expanded.push_str(&resolve(&rest[start + 2..start + end])?);
+        rest = &rest[start + end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Value of a single reference, given without ${ and }.
+fn resolve(reference: &str) -> Result<String, String> {
+    if let Some(path) = reference.strip_prefix("file:") {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("cannot read secret file '{}': {}", path, e))?;
+        let secret = content.trim_end_matches(['\n', '\r']).to_owned();
+        add_secret(&secret);
+        return Ok(secret);
+    }
+
+    let (name, default) = match reference.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (reference, None),
+    };
+
+    match (env::var(name), default) {
+        (Ok(value), Some(default)) if value.is_empty() => Ok(default.to_owned()),
+        (Ok(value), _) => Ok(value),
+        (Err(_), Some(default)) => Ok(default.to_owned()),
+        (Err(_), None) => Err(format!("references '{}' which is not set", name)),
+    }
+}
+
+fn add_secret(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secrets.iter().any(|known| known == secret) {
+        secrets.push(secret.to_owned());
+        secrets.sort_by_key(|known| std::cmp::Reverse(known.len()));
+    }
+}
+
+/// Hide the secrets found in a message before it is shown.
+pub fn redact(message: &str) -> String {
+    let secrets = SECRETS.lock().unwrap();
+    secrets.iter().fold(message.to_owned(), |message, secret| {
+        message.replace(secret.as_str(), REDACTED)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped_references_are_kept() {
+        assert_eq!(expand("$${VAR}").as_deref(), Ok("${VAR}"));
+        assert_eq!(expand("a $${VAR} b $").as_deref(), Ok("a ${VAR} b $"));
+        assert_eq!(
+            expand("$${SHRIMP_TEST_UNSET}${SHRIMP_TEST_UNSET:-x}").as_deref(),
+            Ok("${SHRIMP_TEST_UNSET}x")
+        );
+    }
+
+    #[test]
+    fn defaults_replace_unset_or_empty_variables() {
+        env::set_var("SHRIMP_TEST_EMPTY", "");
+        env::set_var("SHRIMP_TEST_SET", "value");
+
+        assert_eq!(
+            expand("${SHRIMP_TEST_UNSET:-default}").as_deref(),
+            Ok("default")
+        );
+        assert_eq!(
+            expand("${SHRIMP_TEST_EMPTY:-default}").as_deref(),
+            Ok("default")
+        );
+        assert_eq!(
+            expand("${SHRIMP_TEST_SET:-default}").as_deref(),
+            Ok("value")
+        );
+        assert_eq!(expand("${SHRIMP_TEST_EMPTY}").as_deref(), Ok(""));
+        assert_eq!(expand("${SHRIMP_TEST_UNSET:-}").as_deref(), Ok(""));
+        assert_eq!(expand("${SHRIMP_TEST_UNSET:-a:-b}").as_deref(), Ok("a:-b"));
+    }
+
+    #[test]
+    fn reports_unset_and_unterminated_references() {
+        assert!(expand("${SHRIMP_TEST_UNSET}").is_err());
+        assert!(expand("${SHRIMP_TEST_SET").is_err());
+    }
+
+    #[test]
+    fn file_references_are_secrets() {
+        let path = env::temp_dir().join(format!("shrimp-secret-{}", std::process::id()));
+        fs::write(&path, "s3cr3t-token\n").unwrap();
+
+        let expanded = expand(&format!("Bearer ${{file:{}}}", path.display()));
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(expanded.as_deref(), Ok("Bearer s3cr3t-token"));
+        assert_eq!(redact("header: Bearer s3cr3t-token"), "header: Bearer ***");
+    }
+
+    #[test]
+    fn interpolate_reports_every_failure() {
+        let mut table: toml::Table = toml::from_str(
+            r#"
+            a = "${SHRIMP_TEST_UNSET}"
+            b = { c = ["ok", "${SHRIMP_TEST_UNSET}"] }
+            d = "${SHRIMP_TEST_UNSET:-kept}"
+            "#,
+        )
+        .unwrap();
+
+        let paths: Vec<String> = interpolate(&mut table)
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        assert_eq!(paths, vec!["a".to_owned(), "b.c".to_owned()]);
+        assert_eq!(table["d"].as_str(), Some("kept"));
+    }
+}
@@ -0,0 +1,216 @@
+/*!
+Probe metrics for the collectd exec plugin, or other outputs.
+
+The plugin module along with config::PluginConfig make the API needed to
+write plugins, which can then be registered next to the built-in ones and
+executed by run().
+*/
+
+use std::env;
+use std::mem;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::Arc;
+
+use crate::utils::duration;
+
+mod cli;
+pub mod config;
+#[cfg(feature = "dynamic")]
+pub mod dynamic;
+mod interpolation;
+pub mod output;
+pub mod plugin;
+pub mod plugins;
+pub mod plugins_list;
+mod pool;
+mod scheduler;
+mod threshold;
+mod utils;
+
+/**
+Run shrimp with the given plugins, as the collectd-shrimp executable does.
+The command and the configuration file are taken from the command line, see
+cli::USAGE.
+
+A downstream executable can add its own plugins to the built-in ones:
+```no_run
+use collectd_shrimp::plugins_list::Registration;
+
+let mut registry = collectd_shrimp::plugins::registry();
+// registry.push(Registration::of::<my_probe::Settings>());
+collectd_shrimp::run(registry);
+```
+*/
+pub fn run(registry: Vec<plugins_list::Registration>) {
+    let args = cli::parse(env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("error: {}\n\n{}", e, cli::USAGE);
+        exit(1);
+    });
+
+    match &args.command {
+        cli::Command::Help => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        cli::Command::ListPlugins => {
+            plugins_list::list_plugins(&registry);
+            return;
+        }
+        cli::Command::Describe(name) => {
+            plugins_list::describe(&registry, name).unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                exit(1);
+            });
+            return;
+        }
+        cli::Command::Run | cli::Command::Check | cli::Command::Once => (),
+    }
+    let check = matches!(args.command, cli::Command::Check);
+
+    // find the config file according to the OS
+    let config_path = args.config_path.unwrap_or_else(|| {
+        let mut etc: PathBuf = match env::consts::OS {
+            "freebsd" => "/usr/local/etc".into(),
+            _ => "/etc".into(),
+        };
+        etc.push("collectd-shrimp.toml");
+        etc
+    });
+
+    // load/parse the config
+    let mut config = config::config(&config_path).unwrap_or_else(|e| {
+        eprintln!(
+            "error: cannot load configuration file '{}': {}",
+            config_path.display(),
+            interpolation::redact(&e.to_string())
+        );
+        exit(1);
+    });
+
+    // the problems found past this point are all reported before exiting
+    let mut errors: Vec<String> = mem::take(&mut config.errors);
+
+    // plugins loaded from shared objects are registered next to the built-in ones
+    #[cfg(feature = "dynamic")]
+    let mut registry = registry;
+    #[cfg(feature = "dynamic")]
+    for path in config.libraries.take().unwrap_or_default() {
+        let registration = match dynamic::load(&path) {
+            Ok(registration) => registration,
+            Err(e) => {
+                let e = format!("cannot load plugin library '{}': {}", path.display(), e);
+                errors.push(config::located(&config.locations, "libraries", e));
+                continue;
+            }
+        };
+
+        if registry.iter().any(|r| r.name == registration.name) {
+            let e = format!(
+                "cannot load plugin library '{}': plugin '{}' is already registered",
+                path.display(),
+                registration.name
+            );
+            errors.push(config::located(&config.locations, "libraries", e));
+            continue;
+        }
+
+        registry.push(registration);
+    }
+
+    // the command line takes precedence over the env variables set by collectd
+    let hostname = args.hostname.or_else(|| env::var("COLLECTD_HOSTNAME").ok());
+    let interval = args.interval.or_else(|| env::var("COLLECTD_INTERVAL").ok());
+    let (hostname, interval) = match (hostname, interval) {
+        (Some(hostname), Some(interval)) => (hostname, interval),
+        // checking the configuration does not depend on them
+        (hostname, interval) if check => (
+            hostname.unwrap_or("localhost".to_owned()),
+            interval.unwrap_or("10".to_owned()),
+        ),
+        _ => {
+            eprintln!(
+                "
+            error: cannot read env variable COLLECTD_HOSTNAME and COLLECTD_INTERVAL
+                   these should be set either by collectd, or by --hostname and --interval if testing the probe"
+            );
+            exit(1);
+        }
+    };
+
+    let interval_duration = match interval.parse::<f64>().ok().and_then(duration::positive) {
+        Some(interval_duration) => interval_duration,
+        None => {
+            eprintln!(
+                "error: cannot parse interval '{}' as a positive number of seconds",
+                interval
+            );
+            exit(1);
+        }
+    };
+
+    let concurrency = config.concurrency.unwrap_or(1);
+    if concurrency == 0 {
+        let e = "concurrency must be at least 1".to_owned();
+        errors.push(config::located(&config.locations, "concurrency", e));
+    }
+
+    let shutdown_timeout = duration::non_negative(config.shutdown_timeout.unwrap_or(5.0));
+    if shutdown_timeout.is_none() {
+        let e = "shutdown_timeout must be a positive number of seconds".to_owned();
+        errors.push(config::located(&config.locations, "shutdown_timeout", e));
+    }
+
+    // outputs are only checked here, they are created once everything is valid
+    errors.extend(output::check(config.output.as_ref(), &config.locations));
+
+    // from the configuration, we assemble a vector of plugin instance to execute
+    let plugins = plugins_list::Plugins::new(registry, hostname, interval_duration);
+    let plugin_instances = match plugins.load(config.plugins, &config.locations, |_, _| false) {
+        Ok(plugin_instances) => plugin_instances,
+        Err(plugin_errors) => {
+            errors.extend(plugin_errors);
+            vec![]
+        }
+    };
+
+    if !errors.is_empty() {
+        for e in &errors {
+            eprintln!("error: {}", interpolation::redact(e));
+        }
+        exit(1);
+    }
+
+    // outputs are not created when checking, they may listen or open files
+    if check {
+        println!(
+            "configuration file '{}' is valid, {} plugin instance(s)",
+            config_path.display(),
+            plugin_instances.len()
+        );
+        return;
+    }
+
+    let output = output::load(config.output.take(), &config.locations).unwrap_or_else(|errors| {
+        for e in &errors {
+            eprintln!("error: {}", interpolation::redact(e));
+        }
+        exit(1);
+    });
+
+    if let cli::Command::Once = args.command {
+        scheduler::once(plugin_instances, concurrency, Arc::new(output));
+        return;
+    }
+
+    scheduler::Scheduler::new(
+        plugins,
+        config_path,
+        plugin_instances,
+        config.restart_only,
+        concurrency,
+        shutdown_timeout.unwrap(),
+        Arc::new(output),
+    )
+    .run();
+}
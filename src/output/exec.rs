@@ -0,0 +1,68 @@
+use serde::Deserialize;
+use std::io::{self, Write};
+use std::time;
+
+use crate::output::{Notification, Output, Sample};
+
+/// The exec output has no setting for now.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {}
+
+/// Print PUTVAL and PUTNOTIF commands on stdout for the collectd exec plugin.
+pub struct Exec {}
+
+impl Exec {
+    pub fn new(_settings: Settings) -> Self {
+        Self {}
+    }
+}
+
+impl Output for Exec {
+    /**
+    Each line is written with a single println! call, which holds the stdout
+    lock for the whole line. So lines printed by instances executing concurrently
+    never interleave.
+    */
+    fn write(&self, sample: &Sample) {
+        let Sample { host, plugin, plugin_instance, r#type, .. } = sample;
+        let interval = sample.interval.as_secs_f64();
+        let time = seconds(sample.time);
+        let value = sample.values.join(":");
+
+        match sample.type_instance {
+            Some(type_instance) => println!(
+                "PUTVAL {host}/{plugin}-{plugin_instance}/{type}-\"{type_instance}\" interval={interval} {time}:{value}"
+            ),
+            None => println!(
+                "PUTVAL {host}/{plugin}-{plugin_instance}/{type} interval={interval} {time}:{value}"
+            ),
+        };
+    }
+
+    fn notify(&self, notification: &Notification) {
+        let Notification {
+            severity, host, plugin, plugin_instance, r#type, ..
+        } = notification;
+        let time = seconds(notification.time);
+        let message = notification.message.replace('"', "'");
+
+        match notification.type_instance {
+            Some(type_instance) => println!(
+                "PUTNOTIF severity={severity} time={time} host={host} plugin={plugin} plugin_instance={plugin_instance} type={type} type_instance=\"{type_instance}\" message=\"{message}\""
+            ),
+            None => println!(
+                "PUTNOTIF severity={severity} time={time} host={host} plugin={plugin} plugin_instance={plugin_instance} type={type} message=\"{message}\""
+            ),
+        };
+    }
+
+    fn flush(&self) {
+        io::stdout().flush().unwrap();
+    }
+}
+
+/// Format a time with microsecond precision, collectd accepts fractional epoch seconds.
+fn seconds(time: time::Duration) -> String {
+    format!("{}.{:06}", time.as_secs(), time.subsec_micros())
+}
@@ -0,0 +1,388 @@
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::time;
+
+use crate::output::udp::{send_lines, udp_socket};
+use crate::output::{warn, Output, Sample};
+
+/// Default port of the carbon plaintext receiver.
+const DEFAULT_PORT: u16 = 2003;
+
+/// Default template of the metric path.
+const DEFAULT_TEMPLATE: &str = "{host}.{plugin}.{instance}.{type}.{type_instance}";
+
+/// Default number of lines kept while the connection is down.
+const DEFAULT_BUFFER_SIZE: usize = 10000;
+
+/// Minimum delay between two connection attempts.
+const RECONNECT_DELAY: time::Duration = time::Duration::from_secs(10);
+
+/// Maximum time to establish the TCP connection.
+const CONNECT_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+/**
+Maximum time to send the lines over TCP. Lines are sent with the connection
+locked, from the workers and the scheduler, so a server that stops reading
+must not block them.
+*/
+const WRITE_TIMEOUT: time::Duration = time::Duration::from_secs(1);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+/// Settings of the Graphite output.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Address of the carbon server.
+    pub server: String,
+
+    /// Port of the carbon plaintext receiver, 2003 by default.
+    pub port: Option<u16>,
+
+    /// Either "tcp" (default) or "udp".
+    pub protocol: Option<Protocol>,
+
+    /**
+    Template of the metric path, using the {host}, {plugin}, {instance},
+    {type}, {type_instance} and {ds} placeholders. Each part is escaped so
+    that it does not contain any dot. Empty parts are dropped from the path
+    and for types with several data sources, the index of the data source
+    is appended to the path unless {ds} is used.
+    */
+    pub template: Option<String>,
+
+    /// Maximum number of lines kept while the TCP connection is down, 10000 by default.
+    pub buffer_size: Option<usize>,
+}
+
+/// Connection along with the lines waiting to be sent.
+struct Connection {
+    stream: Option<TcpStream>,
+    last_attempt: Option<time::Instant>,
+    lines: VecDeque<String>,
+    dropped: usize,
+}
+
+/// Write the values as Graphite plaintext lines to a carbon server.
+pub struct Graphite {
+    address: (String, u16),
+    udp: Option<UdpSocket>,
+    template: String,
+    buffer_size: usize,
+    connection: Mutex<Connection>,
+}
+
+impl Graphite {
+    pub fn new(settings: Settings) -> Result<Self, Box<dyn Error>> {
+        let address = (settings.server, settings.port.unwrap_or(DEFAULT_PORT));
+
+        let udp = match settings.protocol.unwrap_or(Protocol::Tcp) {
+            Protocol::Tcp => None,
+            Protocol::Udp => Some(udp_socket((address.0.as_str(), address.1))?),
+        };
+
+        Ok(Self {
+            address,
+            udp,
+            template: settings.template.unwrap_or(DEFAULT_TEMPLATE.to_owned()),
+            buffer_size: settings.buffer_size.unwrap_or(DEFAULT_BUFFER_SIZE),
+            connection: Mutex::new(Connection {
+                stream: None,
+                last_attempt: None,
+                lines: VecDeque::new(),
+                dropped: 0,
+            }),
+        })
+    }
+
+    /// Build the metric path for one data source of the sample.
+    fn path(&self, sample: &Sample, ds: Option<usize>) -> String {
+        let ds_str = ds.map(|ds| ds.to_string()).unwrap_or_default();
+        let mut path = self
+            .template
+            .replace("{host}", &escape(sample.host))
+            .replace("{plugin}", &escape(sample.plugin))
+            .replace("{instance}", &escape(sample.plugin_instance))
+            .replace("{type}", &escape(sample.r#type))
+            .replace(
+                "{type_instance}",
+                &escape(sample.type_instance.unwrap_or("")),
+            );
+
+        if self.template.contains("{ds}") {
+            path = path.replace("{ds}", &ds_str);
+        } else if ds.is_some() {
+            path = format!("{}.{}", path, ds_str);
+        }
+
+        // drop the empty parts
+        path.split('.')
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(".")
+    }
+
+    /// Send the lines over TCP, reconnecting if needed and keeping them on failure.
+    fn send_tcp(&self, connection: &mut Connection) {
+        if connection.stream.is_none() {
+            let retry = connection
+                .last_attempt
+                .is_none_or(|last| last.elapsed() >= RECONNECT_DELAY);
+            if !retry {
+                return;
+            }
+
+            connection.last_attempt = Some(time::Instant::now());
+            match connect(&self.address) {
+                Ok(stream) => connection.stream = Some(stream),
+                Err(e) => {
+                    warn(&format!(
+                        "cannot connect to graphite server '{}:{}': {}",
+                        self.address.0, self.address.1, e
+                    ));
+                    return;
+                }
+            }
+        }
+
+        let stream = connection.stream.as_mut().unwrap();
+        let payload: String = connection.lines.iter().map(String::as_str).collect();
+
+        let (written, result) = write_some(stream, payload.as_bytes());
+        drop_written(&mut connection.lines, written);
+
+        if let Err(e) = result {
+            // including timeouts, the remaining lines are sent again once reconnected
+            warn(&format!("graphite connection lost: {}", e));
+            connection.stream = None;
+        }
+    }
+}
+
+impl Output for Graphite {
+    fn write(&self, sample: &Sample) {
+        let time = sample.time.as_secs();
+        let mut connection = self.connection.lock().unwrap();
+
+        for (index, value) in sample.values.iter().enumerate() {
+            // graphite has no notion of undefined value
+            if value == "U" {
+                continue;
+            }
+
+            let ds = if sample.values.len() > 1 { Some(index) } else { None };
+            let line = format!("{} {} {}\n", self.path(sample, ds), value, time);
+
+            if connection.lines.len() >= self.buffer_size {
+                connection.lines.pop_front();
+                connection.dropped += 1;
+            }
+            connection.lines.push_back(line);
+        }
+    }
+
+    fn flush(&self) {
+        let mut connection = self.connection.lock().unwrap();
+
+        if connection.dropped > 0 {
+            warn(&format!(
+                "graphite buffer full, dropped {} line(s)",
+                connection.dropped
+            ));
+            connection.dropped = 0;
+        }
+
+        if connection.lines.is_empty() {
+            return;
+        }
+
+        match &self.udp {
+            Some(socket) => {
+                if let Err(e) = send_lines(socket, connection.lines.drain(..)) {
+                    warn(&format!("cannot send graphite datagram: {}", e));
+                }
+            }
+            None => self.send_tcp(&mut connection),
+        }
+    }
+}
+
+fn connect(address: &(String, u16)) -> Result<TcpStream, Box<dyn Error>> {
+    let mut last_error: Box<dyn Error> = "cannot resolve address".into();
+
+    for address in (address.0.as_str(), address.1).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => {
+                stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(e) => last_error = e.into(),
+        }
+    }
+
+    Err(last_error)
+}
+
+/**
+Write as much of the payload as possible, unlike write_all() which does not
+tell how much was written before failing. Returns the number of bytes written
+along with the error that stopped the writing, if any.
+*/
+fn write_some(stream: &mut impl Write, payload: &[u8]) -> (usize, io::Result<()>) {
+    let mut written = 0;
+
+    while written < payload.len() {
+        match stream.write(&payload[written..]) {
+            Ok(0) => return (written, Err(io::ErrorKind::WriteZero.into())),
+            Ok(count) => written += count,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return (written, Err(e)),
+        }
+    }
+
+    (written, Ok(()))
+}
+
+/**
+Drop the lines entirely written out of the given number of bytes. A line that
+was cut short is kept, it is sent again whole on the next connection since the
+server discards what it received of it along with the broken connection.
+*/
+fn drop_written(lines: &mut VecDeque<String>, mut written: usize) {
+    while let Some(line) = lines.front() {
+        if line.len() > written {
+            break;
+        }
+
+        written -= line.len();
+        lines.pop_front();
+    }
+}
+
+/// Replace the characters that would break the metric path.
+fn escape(part: &str) -> String {
+    part.chars()
+        .map(|c| if c == '.' || c == '/' || c.is_whitespace() { '_' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::test_sample;
+
+    fn graphite(template: Option<&str>) -> Graphite {
+        Graphite::new(Settings {
+            server: "localhost".to_owned(),
+            port: None,
+            protocol: None,
+            template: template.map(str::to_owned),
+            buffer_size: None,
+        })
+        .unwrap()
+    }
+
+    /// Stream accepting a given number of bytes at most in each write, then failing.
+    struct Partial {
+        written: Vec<u8>,
+        writes: Vec<usize>,
+    }
+
+    impl Write for Partial {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if self.writes.is_empty() {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+
+            let count = self.writes.remove(0).min(buf.len());
+            self.written.extend_from_slice(&buf[..count]);
+            Ok(count)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn path_follows_the_template() {
+        let values = vec!["1".to_owned()];
+        let sample = test_sample(&values);
+
+        assert_eq!(graphite(None).path(&sample, None), "h.cpu.0.percent.user");
+        assert_eq!(
+            graphite(None).path(&sample, Some(1)),
+            "h.cpu.0.percent.user.1"
+        );
+        assert_eq!(
+            graphite(Some("{plugin}.{ds}.{type}")).path(&sample, Some(0)),
+            "cpu.0.percent"
+        );
+    }
+
+    #[test]
+    fn path_drops_empty_parts() {
+        let values = vec!["1".to_owned()];
+        let sample = Sample {
+            plugin_instance: "",
+            type_instance: None,
+            ..test_sample(&values)
+        };
+
+        assert_eq!(graphite(None).path(&sample, None), "h.cpu.percent");
+        assert_eq!(graphite(Some("{plugin}.{ds}")).path(&sample, None), "cpu");
+    }
+
+    #[test]
+    fn escapes_dots_slashes_and_whitespace() {
+        assert_eq!(escape("a.b/c d\te"), "a_b_c_d_e");
+        assert_eq!(escape("sda-1"), "sda-1");
+
+        let values = vec!["1".to_owned()];
+        let sample = Sample {
+            host: "web.example.com",
+            type_instance: Some("/var/log"),
+            ..test_sample(&values)
+        };
+        assert_eq!(
+            graphite(None).path(&sample, None),
+            "web_example_com.cpu.0.percent._var_log"
+        );
+    }
+
+    #[test]
+    fn partial_writes_keep_the_lines_not_written_entirely() {
+        let mut lines: VecDeque<String> = ["a 1 0\n", "b 2 0\n", "c 3 0\n"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        let payload: String = lines.iter().map(String::as_str).collect();
+
+        let mut stream = Partial { written: vec![], writes: vec![4, 5] };
+        let (written, result) = write_some(&mut stream, payload.as_bytes());
+        assert_eq!(written, 9);
+        assert!(result.is_err());
+        assert_eq!(stream.written, b"a 1 0\nb 2");
+
+        drop_written(&mut lines, written);
+        assert_eq!(lines, ["b 2 0\n", "c 3 0\n"]);
+
+        let payload: String = lines.iter().map(String::as_str).collect();
+        let mut stream = Partial { written: vec![], writes: vec![100] };
+        let (written, result) = write_some(&mut stream, payload.as_bytes());
+        assert!(result.is_ok());
+        assert_eq!(stream.written, b"b 2 0\nc 3 0\n");
+
+        drop_written(&mut lines, written);
+        assert!(lines.is_empty());
+    }
+}
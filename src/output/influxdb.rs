@@ -0,0 +1,324 @@
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::mem;
+use std::net::UdpSocket;
+use std::sync::Mutex;
+use std::time;
+
+use crate::output::udp::{send_lines, udp_socket};
+use crate::output::{warn, Output, Sample};
+use crate::utils::duration;
+
+/// Default number of lines sent at once.
+const DEFAULT_BATCH_SIZE: usize = 5000;
+
+/// Default maximum time spent by a line in the batch, in seconds.
+const DEFAULT_FLUSH_INTERVAL: f64 = 10.0;
+
+/// Default timeout of the HTTP requests, in seconds.
+const DEFAULT_TIMEOUT: f64 = 5.0;
+
+/// Settings of the InfluxDB output.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /**
+    Where to write the lines, either the HTTP write endpoint such as
+    "http://localhost:8086/write?db=collectd" or a UDP listener such
+    as "udp://localhost:8089".
+    */
+    pub url: String,
+
+    /// Number of lines sent at once, 5000 by default.
+    pub batch_size: Option<usize>,
+
+    /**
+    Maximum time in seconds before a batch is sent even if not full, 10 by
+    default. It is only checked when a plugin instance completes, so a batch
+    may wait up to the interval of the instances on top of it.
+    */
+    pub flush_interval: Option<f64>,
+
+    /// Timeout of the HTTP requests in seconds, 5 by default.
+    pub timeout: Option<f64>,
+}
+
+/// Where the batches are sent.
+enum Endpoint {
+    Http { agent: ureq::Agent, url: String },
+    Udp(UdpSocket),
+}
+
+/// Lines waiting to be sent.
+struct Batch {
+    lines: Vec<String>,
+    since: time::Instant,
+}
+
+/**
+Write the values using the InfluxDB line protocol.
+The plugin name is used as measurement, the rest of the identifier as tags and
+the values as fields named "value" (or "value0", "value1", ... for types with
+several data sources). Timestamps are in nanoseconds.
+*/
+pub struct InfluxDb {
+    endpoint: Endpoint,
+    batch_size: usize,
+    flush_interval: time::Duration,
+    batch: Mutex<Batch>,
+}
+
+/// Check the settings without creating the socket.
+pub fn check(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    let schemes = ["http://", "https://", "udp://"];
+    if !schemes
+        .iter()
+        .any(|scheme| settings.url.starts_with(scheme))
+    {
+        return Err(format!(
+            "unsupported url '{}', expected http://, https:// or udp://",
+            settings.url
+        )
+        .into());
+    }
+
+    if settings
+        .timeout
+        .is_some_and(|timeout| duration::positive(timeout).is_none())
+    {
+        return Err("timeout must be a positive number of seconds".into());
+    }
+
+    if settings
+        .flush_interval
+        .is_some_and(|flush_interval| duration::non_negative(flush_interval).is_none())
+    {
+        return Err("flush_interval must be a positive number of seconds".into());
+    }
+
+    Ok(())
+}
+
+impl InfluxDb {
+    pub fn new(settings: Settings) -> Result<Self, Box<dyn Error>> {
+        check(&settings)?;
+
+        let endpoint = if let Some(address) = settings.url.strip_prefix("udp://") {
+            Endpoint::Udp(udp_socket(address)?)
+        } else {
+            // checked above, either http:// or https://
+            let timeout = duration::positive(settings.timeout.unwrap_or(DEFAULT_TIMEOUT));
+            let agent = ureq::AgentBuilder::new()
+                .user_agent("collectd-shrimp")
+                .timeout(timeout.unwrap())
+                .build();
+            Endpoint::Http { agent, url: settings.url }
+        };
+
+        let batch_size = settings.batch_size.unwrap_or(DEFAULT_BATCH_SIZE).max(1);
+        let flush_interval =
+            duration::non_negative(settings.flush_interval.unwrap_or(DEFAULT_FLUSH_INTERVAL))
+                .unwrap();
+
+        Ok(Self {
+            endpoint,
+            batch_size,
+            flush_interval,
+            batch: Mutex::new(Batch {
+                lines: Vec::with_capacity(batch_size),
+                since: time::Instant::now(),
+            }),
+        })
+    }
+
+    /// Send the lines, without holding the lock of the batch so that writers are not blocked.
+    fn send(&self, lines: Vec<String>) {
+        let result = match &self.endpoint {
+            Endpoint::Http { agent, url } => agent
+                .post(url)
+                .send_string(&lines.concat())
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            Endpoint::Udp(socket) => send_lines(socket, &lines).map_err(|e| e.to_string()),
+        };
+
+        if let Err(e) = result {
+            warn(&format!(
+                "cannot write {} line(s) to influxdb: {}",
+                lines.len(),
+                e
+            ));
+        }
+    }
+}
+
+impl Batch {
+    /// Take the lines out of the batch, which starts over.
+    fn take(&mut self) -> Vec<String> {
+        self.since = time::Instant::now();
+        mem::take(&mut self.lines)
+    }
+}
+
+impl Output for InfluxDb {
+    fn write(&self, sample: &Sample) {
+        let line = match line(sample) {
+            Some(line) => line,
+            None => return,
+        };
+
+        let full = {
+            let mut batch = self.batch.lock().unwrap();
+            if batch.lines.is_empty() {
+                batch.since = time::Instant::now();
+            }
+            batch.lines.push(line);
+
+            (batch.lines.len() >= self.batch_size).then(|| batch.take())
+        };
+
+        if let Some(lines) = full {
+            self.send(lines);
+        }
+    }
+
+    fn flush(&self) {
+        let due = {
+            let mut batch = self.batch.lock().unwrap();
+            let due = !batch.lines.is_empty() && batch.since.elapsed() >= self.flush_interval;
+            due.then(|| batch.take())
+        };
+
+        if let Some(lines) = due {
+            self.send(lines);
+        }
+    }
+
+    fn close(&self) {
+        let lines = self.batch.lock().unwrap().take();
+
+        if !lines.is_empty() {
+            self.send(lines);
+        }
+    }
+}
+
+/// Format a sample as a line, undefined values are left out.
+fn line(sample: &Sample) -> Option<String> {
+    let mut fields = String::new();
+    for (index, value) in sample.values.iter().enumerate() {
+        // the line protocol has neither undefined nor infinite values
+        match value.parse::<f64>() {
+            _ if value == "U" => continue,
+            Ok(number) if number.is_nan() => continue,
+            Ok(number) if number.is_finite() => (),
+            _ => {
+                warn(&format!(
+                    "cannot write value '{}' of {}/{}-{}/{} to influxdb, not a finite number",
+                    value, sample.host, sample.plugin, sample.plugin_instance, sample.r#type
+                ));
+                return None;
+            }
+        }
+
+        let separator = if fields.is_empty() { "" } else { "," };
+        if sample.values.len() > 1 {
+            let _ = write!(fields, "{}value{}={}", separator, index, value);
+        } else {
+            let _ = write!(fields, "{}value={}", separator, value);
+        }
+    }
+
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut line = escape(sample.plugin, ", ");
+    let _ = write!(line, ",host={}", escape(sample.host, ",= "));
+    if !sample.plugin_instance.is_empty() {
+        let _ = write!(line, ",instance={}", escape(sample.plugin_instance, ",= "));
+    }
+    let _ = write!(line, ",type={}", escape(sample.r#type, ",= "));
+    if let Some(type_instance) = sample.type_instance.filter(|t| !t.is_empty()) {
+        let _ = write!(line, ",type_instance={}", escape(type_instance, ",= "));
+    }
+    let _ = writeln!(line, " {} {}", fields, sample.time.as_nanos());
+
+    Some(line)
+}
+
+/// Escape the given characters with a backslash.
+fn escape(value: &str, characters: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        if characters.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::test_sample;
+
+    fn line_of(values: &[&str]) -> Option<String> {
+        let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        line(&test_sample(&values))
+    }
+
+    #[test]
+    fn line_has_tags_fields_and_nanoseconds() {
+        assert_eq!(
+            line_of(&["42"]).as_deref(),
+            Some("cpu,host=h,instance=0,type=percent,type_instance=user value=42 1700000000500000000\n")
+        );
+
+        let values = vec!["42".to_owned()];
+        let sample = Sample {
+            plugin_instance: "",
+            type_instance: None,
+            ..test_sample(&values)
+        };
+        assert_eq!(
+            line(&sample).as_deref(),
+            Some("cpu,host=h,type=percent value=42 1700000000500000000\n")
+        );
+    }
+
+    #[test]
+    fn undefined_and_nan_values_are_left_out() {
+        let fields = line_of(&["1", "U", "NaN", "4"]).unwrap();
+        assert!(fields.contains(" value0=1,value3=4 "));
+
+        assert_eq!(line_of(&["U", "nan"]), None);
+    }
+
+    #[test]
+    fn infinite_values_drop_the_sample() {
+        assert_eq!(line_of(&["1", "inf"]), None);
+        assert_eq!(line_of(&["-infinity"]), None);
+        assert_eq!(line_of(&["abc"]), None);
+    }
+
+    #[test]
+    fn escapes_the_given_characters() {
+        assert_eq!(escape("a b,c=d", ",= "), "a\\ b\\,c\\=d");
+        assert_eq!(escape("a=b", ", "), "a=b");
+
+        let values = vec!["1".to_owned()];
+        let sample = Sample {
+            plugin: "my plugin",
+            host: "a,b",
+            ..test_sample(&values)
+        };
+        assert!(line(&sample)
+            .unwrap()
+            .starts_with("my\\ plugin,host=a\\,b,"));
+    }
+}
@@ -0,0 +1,291 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::output::{warn, Output, Sample};
+
+/// Default number of rotated files that are kept.
+const DEFAULT_KEEP: usize = 5;
+
+/// Settings of the JSON output.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// File to append the samples to, stdout is used if not specified.
+    pub path: Option<PathBuf>,
+
+    /**
+    Rotate the file once it reaches this size in bytes. The current file is
+    renamed with a ".1" suffix, the previous ".1" becomes ".2" and so on.
+    */
+    pub max_size: Option<u64>,
+
+    /// Number of rotated files that are kept, 5 by default.
+    pub keep: Option<usize>,
+}
+
+/// One line of output.
+#[derive(Serialize)]
+struct Record<'a> {
+    host: &'a str,
+    plugin: &'a str,
+    plugin_instance: &'a str,
+    r#type: &'a str,
+    type_instance: Option<&'a str>,
+    interval: f64,
+    time: f64,
+    values: Vec<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<&'a str>,
+}
+
+/// File being written along with its current size.
+struct LogFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    size: u64,
+    max_size: Option<u64>,
+    keep: usize,
+}
+
+enum Destination {
+    Stdout,
+    File(LogFile),
+}
+
+/**
+Write one JSON object per sample, to stdout or to a file.
+Samples of targets that failed carry the error, including
+those that were skipped according to the on_error policy.
+*/
+pub struct Json {
+    destination: Mutex<Destination>,
+}
+
+/// Check the settings without opening the file.
+pub fn check(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    if let Some(path) = &settings.path {
+        let directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        };
+        if !directory.is_dir() {
+            return Err(format!("directory of '{}' does not exist", path.display()).into());
+        }
+    }
+
+    Ok(())
+}
+
+impl Json {
+    pub fn new(settings: Settings) -> Result<Self, Box<dyn Error>> {
+        check(&settings)?;
+
+        let destination = match settings.path {
+            None => Destination::Stdout,
+            Some(path) => {
+                let file = open(&path)?;
+                let size = file.metadata()?.len();
+
+                Destination::File(LogFile {
+                    path,
+                    writer: BufWriter::new(file),
+                    size,
+                    max_size: settings.max_size,
+                    keep: settings.keep.unwrap_or(DEFAULT_KEEP),
+                })
+            }
+        };
+
+        Ok(Self { destination: Mutex::new(destination) })
+    }
+
+    fn write_record(&self, sample: &Sample) {
+        let record = Record {
+            host: sample.host,
+            plugin: sample.plugin,
+            plugin_instance: sample.plugin_instance,
+            r#type: sample.r#type,
+            type_instance: sample.type_instance,
+            interval: sample.interval.as_secs_f64(),
+            time: sample.time.as_secs_f64(),
+            values: sample
+                .values
+                .iter()
+                .map(|value| json_value(value))
+                .collect(),
+            error: sample.error,
+        };
+
+        let mut line = match serde_json::to_string(&record) {
+            Ok(line) => line,
+            Err(e) => {
+                warn(&format!("cannot serialize sample to json: {}", e));
+                return;
+            }
+        };
+        line.push('\n');
+
+        let result = match &mut *self.destination.lock().unwrap() {
+            // a single write holding the lock, so lines never interleave
+            Destination::Stdout => io::stdout().lock().write_all(line.as_bytes()),
+            Destination::File(file) => file.write(line.as_bytes()),
+        };
+
+        if let Err(e) = result {
+            warn(&format!("cannot write json output: {}", e));
+        }
+    }
+}
+
+impl Output for Json {
+    fn write(&self, sample: &Sample) {
+        self.write_record(sample);
+    }
+
+    fn skip(&self, sample: &Sample) {
+        self.write_record(sample);
+    }
+
+    fn flush(&self) {
+        let result = match &mut *self.destination.lock().unwrap() {
+            Destination::Stdout => io::stdout().flush(),
+            Destination::File(file) => file.writer.flush(),
+        };
+
+        if let Err(e) = result {
+            warn(&format!("cannot flush json output: {}", e));
+        }
+    }
+}
+
+impl LogFile {
+    /// Append a line, rotating the file beforehand if it would grow too large.
+    fn write(&mut self, line: &[u8]) -> io::Result<()> {
+        if let Some(max_size) = self.max_size {
+            if self.size > 0 && self.size + line.len() as u64 > max_size {
+                self.rotate()?;
+            }
+        }
+
+        self.writer.write_all(line)?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+
+        // shift the rotated files, the oldest one is overwritten
+        for index in (1..self.keep).rev() {
+            let from = rotated(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated(&self.path, index + 1))?;
+            }
+        }
+
+        if self.keep > 0 {
+            fs::rename(&self.path, rotated(&self.path, 1))?;
+        } else {
+            fs::remove_file(&self.path)?;
+        }
+
+        self.writer = BufWriter::new(open(&self.path)?);
+        self.size = 0;
+
+        Ok(())
+    }
+}
+
+fn open(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+fn rotated(path: &Path, index: usize) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    PathBuf::from(rotated)
+}
+
+/// Values are written as numbers when possible, undefined values as null.
+fn json_value(value: &str) -> serde_json::Value {
+    if value == "U" {
+        return serde_json::Value::Null;
+    }
+
+    if let Ok(integer) = value.parse::<i64>() {
+        return integer.into();
+    }
+    if let Ok(integer) = value.parse::<u64>() {
+        return integer.into();
+    }
+
+    match value.parse::<f64>() {
+        Ok(float) if float.is_finite() => float.into(),
+        _ => value.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::test_sample;
+
+    #[test]
+    fn values_are_numbers_when_possible() {
+        assert_eq!(json_value("U"), serde_json::Value::Null);
+        assert_eq!(json_value("-42"), serde_json::json!(-42));
+        assert_eq!(
+            json_value("18446744073709551615"),
+            serde_json::json!(u64::MAX)
+        );
+        assert_eq!(json_value("1.5"), serde_json::json!(1.5));
+        assert_eq!(json_value("abc"), serde_json::json!("abc"));
+    }
+
+    #[test]
+    fn nan_and_infinite_values_are_strings() {
+        assert_eq!(json_value("NaN"), serde_json::json!("NaN"));
+        assert_eq!(json_value("inf"), serde_json::json!("inf"));
+        assert_eq!(json_value("-infinity"), serde_json::json!("-infinity"));
+    }
+
+    #[test]
+    fn rotation_keeps_the_given_number_of_files() {
+        let dir = std::env::temp_dir().join(format!("shrimp-json-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("values.json");
+
+        let output = Json::new(Settings {
+            path: Some(path.to_owned()),
+            max_size: Some(1),
+            keep: Some(2),
+        })
+        .unwrap();
+        for value in ["1", "2", "3", "4"] {
+            let values = vec![value.to_owned()];
+            output.write(&test_sample(&values));
+        }
+        output.flush();
+
+        let values = |path: &Path| -> Vec<serde_json::Value> {
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(|line| {
+                    serde_json::from_str::<serde_json::Value>(line).unwrap()["values"][0].to_owned()
+                })
+                .collect()
+        };
+        assert_eq!(values(&path), [serde_json::json!(4)]);
+        assert_eq!(values(&rotated(&path, 1)), [serde_json::json!(3)]);
+        assert_eq!(values(&rotated(&path, 2)), [serde_json::json!(2)]);
+        assert!(!rotated(&path, 3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,350 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::time;
+
+use crate::config::{located, Location, OutputConfig};
+
+pub mod exec;
+
+#[cfg(feature = "network")]
+pub mod network;
+
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+
+#[cfg(feature = "graphite")]
+pub mod graphite;
+
+#[cfg(feature = "influxdb")]
+pub mod influxdb;
+
+#[cfg(feature = "json")]
+pub mod json;
+
+#[cfg(any(feature = "network", feature = "graphite", feature = "influxdb"))]
+mod udp;
+
+/**
+A value measured by a plugin instance along with its collectd identifier.
+This is what the plugin instances hand over to the outputs, each output is
+then free to format it the way it wants.
+*/
+pub struct Sample<'a> {
+    pub host: &'a str,
+    pub plugin: &'a str,
+    pub plugin_instance: &'a str,
+    pub r#type: &'a str,
+    pub type_instance: Option<&'a str>,
+
+    /// The interval at which the value is measured.
+    pub interval: time::Duration,
+
+    /// The time of measurement since the epoch.
+    pub time: time::Duration,
+
+    /// One value for each data source of the type.
+    pub values: &'a [String],
+
+    /**
+    Why some of the targets could not be measured. Their values were then
+    replaced according to the on_error policy of the instance.
+    */
+    pub error: Option<&'a str>,
+
+    /// Name of the plugin implementation, the plugin name above may have been changed in the configuration.
+    pub implementation: &'a str,
+
+    /// Description of the plugin implementation.
+    pub desc: &'a str,
+}
+
+/// Severity of a notification, ordered from the least to the most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Okay,
+    Warning,
+    Failure,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Okay => write!(f, "okay"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Failure => write!(f, "failure"),
+        }
+    }
+}
+
+/// A value of a plugin instance changed state according to its thresholds.
+pub struct Notification<'a> {
+    pub severity: Severity,
+    pub host: &'a str,
+    pub plugin: &'a str,
+    pub plugin_instance: &'a str,
+    pub r#type: &'a str,
+    pub type_instance: Option<&'a str>,
+
+    /// The time of the value that caused the notification.
+    pub time: time::Duration,
+
+    /// Human readable description of the state change.
+    pub message: &'a str,
+}
+
+/**
+Trait that must be implemented by all outputs.
+Plugin instances write to the outputs concurrently from the worker pool,
+so outputs must take care of their own synchronization.
+*/
+pub trait Output: Send + Sync {
+    /// Write a sample.
+    fn write(&self, sample: &Sample);
+
+    /**
+    Called instead of write() when the sample could not be measured and was
+    skipped according to the on_error policy. It has no value but an error.
+    */
+    fn skip(&self, _sample: &Sample) {}
+
+    /// Write a notification, outputs that have no notion of it ignore them.
+    fn notify(&self, _notification: &Notification) {}
+
+    /// Flush whatever was buffered so far, called once a plugin instance has been executed.
+    fn flush(&self) {}
+
+    /**
+    Send everything that is still buffered before the program exits, including
+    what flush() would keep for later such as batches that are not due yet.
+    */
+    fn close(&self) {
+        self.flush();
+    }
+}
+
+/**
+Report a problem of an output on stderr. Secrets are redacted since the
+messages may contain them, such as URLs with credentials in error messages.
+*/
+#[cfg(any(
+    feature = "network",
+    feature = "prometheus",
+    feature = "graphite",
+    feature = "influxdb",
+    feature = "json"
+))]
+pub(crate) fn warn(message: &str) {
+    eprintln!("warning: {}", crate::interpolation::redact(message));
+}
+
+/// Write the samples to several outputs at the same time.
+pub struct Outputs {
+    outputs: Vec<Box<dyn Output>>,
+}
+
+impl Output for Outputs {
+    fn write(&self, sample: &Sample) {
+        for output in &self.outputs {
+            output.write(sample);
+        }
+    }
+
+    fn skip(&self, sample: &Sample) {
+        for output in &self.outputs {
+            output.skip(sample);
+        }
+    }
+
+    fn notify(&self, notification: &Notification) {
+        for output in &self.outputs {
+            output.notify(notification);
+        }
+    }
+
+    fn flush(&self) {
+        for output in &self.outputs {
+            output.flush();
+        }
+    }
+
+    fn close(&self) {
+        for output in &self.outputs {
+            output.close();
+        }
+    }
+}
+
+/**
+Check the output section of the configuration without creating the outputs,
+since they may listen or open files. Every problem is reported, along with
+where the output is in the configuration.
+*/
+pub fn check(config: Option<&OutputConfig>, locations: &HashMap<String, Location>) -> Vec<String> {
+    let config = match config {
+        Some(config) => config,
+        None => return vec![],
+    };
+
+    let mut errors: Vec<String> = vec![];
+    if let Err(e) = has_outputs(config) {
+        errors.push(located(locations, "output", e.to_string()));
+    }
+
+    #[cfg(feature = "network")]
+    if let Some(Err(e)) = config.network.as_ref().map(network::check) {
+        errors.push(cannot_configure(locations, "network", e));
+    }
+
+    #[cfg(feature = "prometheus")]
+    if let Some(Err(e)) = config.prometheus.as_ref().map(prometheus::check) {
+        errors.push(cannot_configure(locations, "prometheus", e));
+    }
+
+    #[cfg(feature = "influxdb")]
+    if let Some(Err(e)) = config.influxdb.as_ref().map(influxdb::check) {
+        errors.push(cannot_configure(locations, "influxdb", e));
+    }
+
+    #[cfg(feature = "json")]
+    if let Some(Err(e)) = config.json.as_ref().map(json::check) {
+        errors.push(cannot_configure(locations, "json", e));
+    }
+
+    errors
+}
+
+/**
+Create the outputs found in the output section of the configuration.
+Without output section, we fallback to the exec output.
+*/
+pub fn load(
+    config: Option<OutputConfig>,
+    locations: &HashMap<String, Location>,
+) -> Result<Outputs, Vec<String>> {
+    let config = match config {
+        Some(config) => config,
+        None => {
+            return Ok(Outputs {
+                outputs: vec![Box::new(exec::Exec::new(exec::Settings {}))],
+            })
+        }
+    };
+
+    let mut outputs: Vec<Box<dyn Output>> = vec![];
+    let mut errors: Vec<String> = vec![];
+    if let Err(e) = has_outputs(&config) {
+        errors.push(located(locations, "output", e.to_string()));
+    }
+
+    let mut add = |name: &str, output: Result<Box<dyn Output>, Box<dyn Error>>| match output {
+        Ok(output) => outputs.push(output),
+        Err(e) => errors.push(cannot_configure(locations, name, e)),
+    };
+
+    if let Some(settings) = config.exec {
+        add("exec", Ok(Box::new(exec::Exec::new(settings))));
+    }
+
+    #[cfg(feature = "network")]
+    if let Some(settings) = config.network {
+        add(
+            "network",
+            network::Client::new(settings).map(|o| Box::new(o) as _),
+        );
+    }
+
+    #[cfg(feature = "prometheus")]
+    if let Some(settings) = config.prometheus {
+        add(
+            "prometheus",
+            prometheus::Prometheus::new(settings).map(|o| Box::new(o) as _),
+        );
+    }
+
+    #[cfg(feature = "graphite")]
+    if let Some(settings) = config.graphite {
+        add(
+            "graphite",
+            graphite::Graphite::new(settings).map(|o| Box::new(o) as _),
+        );
+    }
+
+    #[cfg(feature = "influxdb")]
+    if let Some(settings) = config.influxdb {
+        add(
+            "influxdb",
+            influxdb::InfluxDb::new(settings).map(|o| Box::new(o) as _),
+        );
+    }
+
+    #[cfg(feature = "json")]
+    if let Some(settings) = config.json {
+        add("json", json::Json::new(settings).map(|o| Box::new(o) as _));
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    Ok(Outputs { outputs })
+}
+
+/// An output section must configure at least one output.
+fn has_outputs(config: &OutputConfig) -> Result<(), Box<dyn Error>> {
+    let configured = [
+        config.exec.is_some(),
+        #[cfg(feature = "network")]
+        config.network.is_some(),
+        #[cfg(feature = "prometheus")]
+        config.prometheus.is_some(),
+        #[cfg(feature = "graphite")]
+        config.graphite.is_some(),
+        #[cfg(feature = "influxdb")]
+        config.influxdb.is_some(),
+        #[cfg(feature = "json")]
+        config.json.is_some(),
+    ];
+
+    if !configured.contains(&true) {
+        return Err("no output configured in the output section".into());
+    }
+
+    Ok(())
+}
+
+/// Message of an output that cannot be configured.
+fn cannot_configure(
+    locations: &HashMap<String, Location>,
+    name: &str,
+    e: Box<dyn Error>,
+) -> String {
+    let message = format!("cannot configure {} output: {}", name, e);
+    located(locations, &format!("output:{}", name), message)
+}
+
+/// Sample of the tests of the outputs, cpu-0/percent-user of host h with the given values.
+#[cfg(all(
+    test,
+    any(
+        feature = "prometheus",
+        feature = "graphite",
+        feature = "influxdb",
+        feature = "json"
+    )
+))]
+fn test_sample(values: &[String]) -> Sample<'_> {
+    Sample {
+        host: "h",
+        plugin: "cpu",
+        plugin_instance: "0",
+        r#type: "percent",
+        type_instance: Some("user"),
+        interval: time::Duration::from_secs(10),
+        time: time::Duration::new(1_700_000_000, 500_000_000),
+        values,
+        error: None,
+        implementation: "cpu",
+        desc: "CPU usage.",
+    }
+}
@@ -0,0 +1,518 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::UdpSocket;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time;
+
+use crate::output::udp::udp_socket;
+use crate::output::{warn, Output, Sample};
+
+// part types of the collectd binary protocol
+const TYPE_HOST: u16 = 0x0000;
+const TYPE_PLUGIN: u16 = 0x0002;
+const TYPE_PLUGIN_INSTANCE: u16 = 0x0003;
+const TYPE_TYPE: u16 = 0x0004;
+const TYPE_TYPE_INSTANCE: u16 = 0x0005;
+const TYPE_VALUES: u16 = 0x0006;
+const TYPE_TIME_HR: u16 = 0x0008;
+const TYPE_INTERVAL_HR: u16 = 0x0009;
+#[cfg(feature = "network_security")]
+const TYPE_SIGN_SHA256: u16 = 0x0200;
+#[cfg(feature = "network_security")]
+const TYPE_ENCR_AES256: u16 = 0x0210;
+
+/// Default port of the collectd network plugin.
+const DEFAULT_PORT: u16 = 25826;
+
+/// Default maximum size of a packet, same as collectd.
+const DEFAULT_MAX_PACKET_SIZE: usize = 1452;
+
+/// Where to look for types.db when not configured.
+const DEFAULT_TYPES_DB: [&str; 2] = [
+    "/usr/share/collectd/types.db",
+    "/usr/local/share/collectd/types.db",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecurityLevel {
+    None,
+    Sign,
+    Encrypt,
+}
+
+/// Settings of the network output, to send values to a collectd network plugin.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Address of the server running the collectd network plugin.
+    pub server: String,
+
+    /// UDP port of the server, 25826 by default.
+    pub port: Option<u16>,
+
+    /// Either "none" (default), "sign" or "encrypt" as in the network plugin.
+    pub security_level: Option<SecurityLevel>,
+
+    /// Username for signed or encrypted packets.
+    #[cfg_attr(not(feature = "network_security"), allow(dead_code))]
+    pub username: Option<String>,
+
+    /// Password for signed or encrypted packets.
+    #[cfg_attr(not(feature = "network_security"), allow(dead_code))]
+    pub password: Option<String>,
+
+    /**
+    The types.db files used to find out the type of each data source.
+    The protocol encodes gauges, counters and derives differently, so values of
+    a type that cannot be found there are sent as gauges.
+    */
+    pub types_db: Option<Vec<PathBuf>>,
+
+    /// Maximum size of the UDP packets, 1452 bytes by default.
+    pub max_packet_size: Option<usize>,
+}
+
+/// Data source types as encoded in the values part.
+#[derive(Debug, Clone, Copy)]
+enum DsType {
+    Counter = 0,
+    Gauge = 1,
+    Derive = 2,
+    Absolute = 3,
+}
+
+/// Security settings once checked.
+enum Security {
+    None,
+    #[cfg(feature = "network_security")]
+    Sign {
+        username: String,
+        password: String,
+    },
+    #[cfg(feature = "network_security")]
+    Encrypt {
+        username: String,
+        key: [u8; 32],
+    },
+}
+
+/**
+Client sending values to a collectd server.
+Value lists are buffered into a single packet until
+it is full or explicitly flushed.
+*/
+pub struct Client {
+    socket: UdpSocket,
+    security: Security,
+    types: HashMap<String, Vec<DsType>>,
+    capacity: usize,
+    buffer: Mutex<Vec<u8>>,
+}
+
+/// What the client needs out of the settings, once checked.
+struct Options {
+    security: Security,
+    types: HashMap<String, Vec<DsType>>,
+    max_packet_size: usize,
+    capacity: usize,
+}
+
+/// Check the settings without creating the socket.
+pub fn check(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    options(settings).map(|_| ())
+}
+
+fn options(settings: &Settings) -> Result<Options, Box<dyn Error>> {
+    let security = security(settings)?;
+    let max_packet_size = settings.max_packet_size.unwrap_or(DEFAULT_MAX_PACKET_SIZE);
+
+    // keep room for the signature or encryption header
+    let overhead = match &security {
+        Security::None => 0,
+        #[cfg(feature = "network_security")]
+        Security::Sign { username, .. } => 4 + 32 + username.len(),
+        #[cfg(feature = "network_security")]
+        Security::Encrypt { username, .. } => 4 + 2 + username.len() + 16 + 20,
+    };
+    if max_packet_size <= overhead {
+        return Err(format!("max_packet_size must be greater than {}", overhead).into());
+    }
+
+    let types = match &settings.types_db {
+        Some(paths) => load_types_db(paths)?,
+        None => {
+            let paths: Vec<PathBuf> = DEFAULT_TYPES_DB
+                .iter()
+                .map(PathBuf::from)
+                .filter(|path| path.exists())
+                .collect();
+            load_types_db(&paths)?
+        }
+    };
+
+    Ok(Options {
+        security,
+        types,
+        max_packet_size,
+        capacity: max_packet_size - overhead,
+    })
+}
+
+impl Client {
+    pub fn new(settings: Settings) -> Result<Self, Box<dyn Error>> {
+        let options = options(&settings)?;
+
+        let socket = udp_socket((
+            settings.server.as_str(),
+            settings.port.unwrap_or(DEFAULT_PORT),
+        ))?;
+
+        Ok(Self {
+            socket,
+            security: options.security,
+            types: options.types,
+            capacity: options.capacity,
+            buffer: Mutex::new(Vec::with_capacity(options.max_packet_size)),
+        })
+    }
+
+    /// Sign or encrypt the payload as configured and send it.
+    fn send_packet(&self, payload: &[u8]) {
+        let result = match &self.security {
+            Security::None => self.socket.send(payload),
+            #[cfg(feature = "network_security")]
+            Security::Sign { username, password } => self
+                .socket
+                .send(&security::sign(username, password, payload)),
+            #[cfg(feature = "network_security")]
+            Security::Encrypt { username, key } => {
+                self.socket.send(&security::encrypt(username, key, payload))
+            }
+        };
+
+        if let Err(e) = result {
+            warn(&format!("cannot send network packet: {}", e));
+        }
+    }
+}
+
+impl Output for Client {
+    /// Encode a value list and queue it for sending.
+    fn write(&self, sample: &Sample) {
+        let Sample { host, plugin, plugin_instance, r#type, .. } = sample;
+        let mut parts: Vec<u8> = Vec::with_capacity(128);
+
+        put_string(&mut parts, TYPE_HOST, host);
+        put_number(&mut parts, TYPE_TIME_HR, cdtime(sample.time));
+        put_number(&mut parts, TYPE_INTERVAL_HR, cdtime(sample.interval));
+        put_string(&mut parts, TYPE_PLUGIN, plugin);
+        put_string(&mut parts, TYPE_PLUGIN_INSTANCE, plugin_instance);
+        put_string(&mut parts, TYPE_TYPE, r#type);
+        put_string(
+            &mut parts,
+            TYPE_TYPE_INSTANCE,
+            sample.type_instance.unwrap_or(""),
+        );
+        if let Err(e) = put_values(&mut parts, self.types.get(*r#type), sample.values) {
+            warn(&format!(
+                "cannot encode {}/{}-{}/{} for the network: {}",
+                host, plugin, plugin_instance, r#type, e
+            ));
+            return;
+        }
+
+        if parts.len() > self.capacity {
+            warn(&format!(
+                "value list {}/{}-{}/{} does not fit in a network packet",
+                host, plugin, plugin_instance, r#type
+            ));
+            return;
+        }
+
+        let mut buffer = self.buffer.lock().unwrap();
+        if buffer.len() + parts.len() > self.capacity {
+            self.send_packet(&buffer);
+            buffer.clear();
+        }
+        buffer.extend_from_slice(&parts);
+    }
+
+    /// Send the values buffered so far.
+    fn flush(&self) {
+        let mut buffer = self.buffer.lock().unwrap();
+
+        if !buffer.is_empty() {
+            self.send_packet(&buffer);
+            buffer.clear();
+        }
+    }
+}
+
+/// Check the security settings.
+fn security(settings: &Settings) -> Result<Security, Box<dyn Error>> {
+    let level = settings
+        .security_level
+        .to_owned()
+        .unwrap_or(SecurityLevel::None);
+
+    match level {
+        SecurityLevel::None => Ok(Security::None),
+        #[cfg(feature = "network_security")]
+        _ => {
+            let (Some(username), Some(password)) = (&settings.username, &settings.password) else {
+                return Err(
+                    "signed or encrypted network output requires a username and a password".into(),
+                );
+            };
+            let (username, password) = (username.to_owned(), password.to_owned());
+
+            Ok(match level {
+                SecurityLevel::Sign => Security::Sign { username, password },
+                _ => Security::Encrypt { username, key: security::key(&password) },
+            })
+        }
+        #[cfg(not(feature = "network_security"))]
+        _ => Err("signed or encrypted network output requires the network_security feature".into()),
+    }
+}
+
+/**
+Parse types.db files.
+Each line contains a type followed by its data sources, such as:
+  if_octets  rx:DERIVE:0:U, tx:DERIVE:0:U
+*/
+fn load_types_db(paths: &[PathBuf]) -> Result<HashMap<String, Vec<DsType>>, Box<dyn Error>> {
+    let mut types = HashMap::new();
+
+    for path in paths {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read '{}': {}", path.display(), e))?;
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let Some((name, data_sources)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+
+            let mut ds_types = vec![];
+            for data_source in data_sources.split(',') {
+                let ds_type = match data_source.trim().split(':').nth(1) {
+                    Some("COUNTER") => DsType::Counter,
+                    Some("GAUGE") => DsType::Gauge,
+                    Some("DERIVE") => DsType::Derive,
+                    Some("ABSOLUTE") => DsType::Absolute,
+                    _ => {
+                        return Err(format!(
+                            "invalid data source '{}' for type '{}' in '{}'",
+                            data_source.trim(),
+                            name,
+                            path.display()
+                        )
+                        .into())
+                    }
+                };
+                ds_types.push(ds_type);
+            }
+
+            types.insert(name.to_owned(), ds_types);
+        }
+    }
+
+    Ok(types)
+}
+
+/// Convert a duration to the collectd high resolution time (2^-30 seconds).
+fn cdtime(duration: time::Duration) -> u64 {
+    (duration.as_secs() << 30) + ((u64::from(duration.subsec_nanos()) << 30) / 1_000_000_000)
+}
+
+fn put_header(buf: &mut Vec<u8>, part_type: u16, length: usize) {
+    buf.extend_from_slice(&part_type.to_be_bytes());
+    buf.extend_from_slice(&(length as u16).to_be_bytes());
+}
+
+fn put_string(buf: &mut Vec<u8>, part_type: u16, value: &str) {
+    put_header(buf, part_type, 4 + value.len() + 1);
+    buf.extend_from_slice(value.as_bytes());
+    buf.push(0);
+}
+
+fn put_number(buf: &mut Vec<u8>, part_type: u16, value: u64) {
+    put_header(buf, part_type, 12);
+    buf.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Encode the values part, gauges are little endian while all the rest is big endian.
+fn put_values(
+    buf: &mut Vec<u8>,
+    ds_types: Option<&Vec<DsType>>,
+    values: &[String],
+) -> Result<(), String> {
+    if let Some(ds_types) = ds_types {
+        if ds_types.len() != values.len() {
+            return Err(format!(
+                "type expects {} value(s) but got {}",
+                ds_types.len(),
+                values.len()
+            ));
+        }
+    }
+
+    put_header(buf, TYPE_VALUES, 4 + 2 + values.len() * 9);
+    buf.extend_from_slice(&(values.len() as u16).to_be_bytes());
+
+    let ds_type = |index: usize| ds_types.map_or(DsType::Gauge, |ds_types| ds_types[index]);
+    for index in 0..values.len() {
+        buf.push(ds_type(index) as u8);
+    }
+
+    for (index, value) in values.iter().enumerate() {
+        let value = value.as_str();
+        let invalid = || format!("invalid value '{}'", value);
+
+        match ds_type(index) {
+            DsType::Gauge => {
+                let gauge =
+                    if value == "U" { f64::NAN } else { value.parse().map_err(|_| invalid())? };
+                buf.extend_from_slice(&f64::to_le_bytes(gauge));
+            }
+            DsType::Derive => {
+                let derive: i64 = value.parse().map_err(|_| invalid())?;
+                buf.extend_from_slice(&derive.to_be_bytes());
+            }
+            DsType::Counter | DsType::Absolute => {
+                let counter: u64 = value.parse().map_err(|_| invalid())?;
+                buf.extend_from_slice(&counter.to_be_bytes());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "network_security")]
+mod security {
+    use aes::cipher::{KeyIvInit, StreamCipher};
+    use hmac::{Hmac, Mac};
+    use sha1::Sha1;
+    use sha2::{Digest, Sha256};
+
+    use super::{put_header, TYPE_ENCR_AES256, TYPE_SIGN_SHA256};
+
+    type Aes256Ofb = ofb::Ofb<aes::Aes256>;
+
+    /// The AES-256 key is the SHA-256 of the password.
+    pub fn key(password: &str) -> [u8; 32] {
+        Sha256::digest(password.as_bytes()).into()
+    }
+
+    /// Prepend a signature part with the HMAC-SHA-256 of the username and payload.
+    pub fn sign(username: &str, password: &str, payload: &[u8]) -> Vec<u8> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(password.as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(username.as_bytes());
+        mac.update(payload);
+
+        let mut packet = Vec::with_capacity(4 + 32 + username.len() + payload.len());
+        put_header(&mut packet, TYPE_SIGN_SHA256, 4 + 32 + username.len());
+        packet.extend_from_slice(&mac.finalize().into_bytes());
+        packet.extend_from_slice(username.as_bytes());
+        packet.extend_from_slice(payload);
+
+        packet
+    }
+
+    /// Wrap the SHA-1 checksum and the payload into an AES-256-OFB encrypted part.
+    pub fn encrypt(username: &str, key: &[u8; 32], payload: &[u8]) -> Vec<u8> {
+        let mut iv = [0u8; 16];
+        getrandom::getrandom(&mut iv).expect("cannot get random bytes for the IV");
+
+        let length = 4 + 2 + username.len() + 16 + 20 + payload.len();
+        let mut packet = Vec::with_capacity(length);
+        put_header(&mut packet, TYPE_ENCR_AES256, length);
+        packet.extend_from_slice(&(username.len() as u16).to_be_bytes());
+        packet.extend_from_slice(username.as_bytes());
+        packet.extend_from_slice(&iv);
+
+        let encrypted_start = packet.len();
+        packet.extend_from_slice(&Sha1::digest(payload));
+        packet.extend_from_slice(payload);
+
+        let mut cipher = Aes256Ofb::new(key.into(), &iv.into());
+        cipher.apply_keystream(&mut packet[encrypted_start..]);
+
+        packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cdtime_is_in_units_of_2_pow_minus_30_seconds() {
+        assert_eq!(cdtime(time::Duration::ZERO), 0);
+        assert_eq!(cdtime(time::Duration::from_secs(1)), 1 << 30);
+        assert_eq!(
+            cdtime(time::Duration::from_millis(1500)),
+            (1 << 30) + (1 << 29)
+        );
+        assert_eq!(
+            cdtime(time::Duration::new(1_700_000_000, 250_000_000)),
+            (1_700_000_000 << 30) + (1 << 28)
+        );
+    }
+
+    #[test]
+    fn values_are_gauges_without_types() {
+        let mut buf = vec![];
+        put_values(&mut buf, None, &["1.5".to_owned()]).unwrap();
+
+        let mut expected = vec![0, 6, 0, 15, 0, 1, 1];
+        expected.extend_from_slice(&1.5f64.to_le_bytes());
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn values_are_encoded_by_type() {
+        let ds_types = vec![
+            DsType::Counter,
+            DsType::Derive,
+            DsType::Absolute,
+            DsType::Gauge,
+        ];
+        let values: Vec<String> = ["42", "-1", "7", "U"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+
+        let mut buf = vec![];
+        put_values(&mut buf, Some(&ds_types), &values).unwrap();
+
+        assert_eq!(buf[..4], [0, 6, 0, 42]);
+        assert_eq!(buf.len(), 42);
+        assert_eq!(buf[4..10], [0, 4, 0, 2, 3, 1]);
+        assert_eq!(buf[10..18], 42u64.to_be_bytes());
+        assert_eq!(buf[18..26], (-1i64).to_be_bytes());
+        assert_eq!(buf[26..34], 7u64.to_be_bytes());
+        assert!(f64::from_le_bytes(buf[34..42].try_into().unwrap()).is_nan());
+    }
+
+    #[test]
+    fn values_must_match_their_types() {
+        let ds_types = vec![DsType::Counter];
+        let mut buf = vec![];
+
+        assert!(put_values(&mut buf, Some(&ds_types), &["1".to_owned(), "2".to_owned()]).is_err());
+        assert!(put_values(&mut buf, Some(&ds_types), &["-1".to_owned()]).is_err());
+        assert!(put_values(&mut buf, Some(&ds_types), &["U".to_owned()]).is_err());
+        assert!(put_values(&mut buf, None, &["abc".to_owned()]).is_err());
+    }
+}
@@ -0,0 +1,229 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time;
+
+use crate::output::{warn, Output, Sample};
+use crate::plugin;
+
+/// Default address of the exporter, same as the collectd exporter.
+const DEFAULT_LISTEN: &str = "0.0.0.0:9103";
+
+/// Samples older than this many intervals are not exported anymore.
+const EXPIRE_INTERVALS: u32 = 2;
+
+/// Settings of the Prometheus exporter.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Address on which to serve the metrics, "0.0.0.0:9103" by default.
+    pub listen: Option<String>,
+}
+
+/// Latest value of a series.
+struct Metric {
+    help: String,
+    value: String,
+    expires: time::Duration,
+}
+
+/// Latest metrics indexed by family name and labels.
+type Metrics = BTreeMap<(String, String), Metric>;
+
+/**
+Serve the latest value of each series on /metrics in the Prometheus text format.
+Each plugin is exported as a metric family named after the plugin implementation
+with its description as help. The collectd identifier parts become labels.
+*/
+pub struct Prometheus {
+    metrics: Arc<Mutex<Metrics>>,
+}
+
+/// Check the settings without listening.
+pub fn check(settings: &Settings) -> Result<(), Box<dyn Error>> {
+    let listen = settings.listen.as_deref().unwrap_or(DEFAULT_LISTEN);
+    let valid = listen
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+    if !valid {
+        return Err(format!("invalid listen address '{}', expected host:port", listen).into());
+    }
+
+    Ok(())
+}
+
+impl Prometheus {
+    pub fn new(settings: Settings) -> Result<Self, Box<dyn Error>> {
+        check(&settings)?;
+
+        let listen = settings.listen.unwrap_or(DEFAULT_LISTEN.to_owned());
+        let listener = TcpListener::bind(&listen)
+            .map_err(|e| format!("cannot listen on '{}': {}", listen, e))?;
+
+        let metrics = Arc::new(Mutex::new(Metrics::new()));
+        let served_metrics = Arc::clone(&metrics);
+
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = serve(stream, &served_metrics) {
+                    warn(&format!("cannot serve Prometheus metrics: {}", e));
+                }
+            }
+        });
+
+        Ok(Self { metrics })
+    }
+}
+
+impl Output for Prometheus {
+    fn write(&self, sample: &Sample) {
+        let family = format!("shrimp_{}", sanitize(sample.implementation));
+        let help = sample.desc.split_whitespace().collect::<Vec<_>>().join(" ");
+        let expires = sample.time + sample.interval * EXPIRE_INTERVALS;
+
+        // the instance label is the scraped target in Prometheus
+        let mut labels = format!(
+            "plugin=\"{}\",plugin_instance=\"{}\",type=\"{}\"",
+            escape(sample.plugin),
+            escape(sample.plugin_instance),
+            escape(sample.r#type)
+        );
+        if let Some(type_instance) = sample.type_instance {
+            let _ = write!(labels, ",type_instance=\"{}\"", escape(type_instance));
+        }
+
+        let mut metrics = self.metrics.lock().unwrap();
+        for (index, value) in sample.values.iter().enumerate() {
+            let value = if value == "U" { "NaN".to_owned() } else { value.to_owned() };
+            let labels = if sample.values.len() > 1 {
+                format!("{},ds=\"{}\"", labels, index)
+            } else {
+                labels.to_owned()
+            };
+
+            metrics.insert(
+                (family.to_owned(), labels),
+                Metric { help: help.to_owned(), value, expires },
+            );
+        }
+    }
+}
+
+/// Answer a single HTTP request.
+fn serve(mut stream: TcpStream, metrics: &Mutex<Metrics>) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(time::Duration::from_secs(5)))?;
+
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => (
+            "200 OK",
+            "text/plain; version=0.0.4",
+            exposition(&mut metrics.lock().unwrap()),
+        ),
+        _ => ("404 Not Found", "text/plain", "not found\n".to_owned()),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.0 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+
+    Ok(())
+}
+
+/// Format the metrics in the text exposition format, dropping the expired ones.
+fn exposition(metrics: &mut Metrics) -> String {
+    let now = plugin::now();
+    metrics.retain(|_, metric| metric.expires > now);
+
+    let mut body = String::new();
+    let mut last_family: Option<&str> = None;
+    for ((family, labels), metric) in metrics.iter() {
+        if last_family != Some(family) {
+            let _ = writeln!(body, "# HELP {} {}", family, escape_help(&metric.help));
+            let _ = writeln!(body, "# TYPE {} untyped", family);
+            last_family = Some(family);
+        }
+        let _ = writeln!(body, "{}{{{}}} {}", family, labels, metric.value);
+    }
+
+    body
+}
+
+/// Replace the characters that are not allowed in metric names.
+fn sanitize(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' || c == ':' { c } else { '_' })
+        .collect()
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn escape_help(help: &str) -> String {
+    help.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::test_sample;
+
+    fn prometheus() -> Prometheus {
+        Prometheus { metrics: Arc::new(Mutex::new(Metrics::new())) }
+    }
+
+    #[test]
+    fn exposition_describes_each_family_once() {
+        let output = prometheus();
+        let values = vec!["1".to_owned(), "U".to_owned()];
+        output.write(&Sample { time: plugin::now(), ..test_sample(&values) });
+        let values = vec!["3".to_owned()];
+        output.write(&Sample {
+            time: plugin::now(),
+            type_instance: None,
+            ..test_sample(&values)
+        });
+
+        let body = exposition(&mut output.metrics.lock().unwrap());
+        assert_eq!(
+            body,
+            "# HELP shrimp_cpu CPU usage.\n\
+             # TYPE shrimp_cpu untyped\n\
+             shrimp_cpu{plugin=\"cpu\",plugin_instance=\"0\",type=\"percent\"} 3\n\
+             shrimp_cpu{plugin=\"cpu\",plugin_instance=\"0\",type=\"percent\",type_instance=\"user\",ds=\"0\"} 1\n\
+             shrimp_cpu{plugin=\"cpu\",plugin_instance=\"0\",type=\"percent\",type_instance=\"user\",ds=\"1\"} NaN\n"
+        );
+    }
+
+    #[test]
+    fn exposition_leaves_expired_values_out() {
+        let output = prometheus();
+        let values = vec!["1".to_owned()];
+        output.write(&test_sample(&values));
+
+        let mut metrics = output.metrics.lock().unwrap();
+        assert_eq!(exposition(&mut metrics), "");
+        assert!(metrics.is_empty());
+    }
+
+    #[test]
+    fn escapes_label_values_and_help() {
+        assert_eq!(escape("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+        assert_eq!(escape_help("a\"b\\c\nd"), "a\"b\\\\c\\nd");
+        assert_eq!(sanitize("http-latency.v2"), "http_latency_v2");
+    }
+}
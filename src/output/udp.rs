@@ -0,0 +1,71 @@
+/*!
+UDP sockets of the outputs, along with the sending of lines for the outputs
+writing a line per value.
+*/
+
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+
+/// Maximum size of the UDP datagrams of the outputs sending lines.
+#[cfg(any(feature = "graphite", feature = "influxdb"))]
+const MAX_DATAGRAM_SIZE: usize = 1400;
+
+/**
+UDP socket sending to the given address. The socket is bound to the unspecified
+address of the family of the destination, an IPv4 socket cannot send to an IPv6
+address and conversely. The resolved addresses are tried in turn.
+*/
+pub(crate) fn udp_socket(address: impl ToSocketAddrs) -> io::Result<UdpSocket> {
+    let mut last_error = io::Error::new(io::ErrorKind::NotFound, "the address resolves to nothing");
+
+    for address in address.to_socket_addrs()? {
+        let local = match address {
+            SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+        };
+
+        let socket = UdpSocket::bind(local).and_then(|socket| {
+            socket.connect(address)?;
+            Ok(socket)
+        });
+        match socket {
+            Ok(socket) => return Ok(socket),
+            Err(e) => last_error = e,
+        }
+    }
+
+    Err(last_error)
+}
+
+/**
+Send lines over UDP, packing as many as possible in each datagram without
+splitting them. Every datagram is sent even if some fail, the last error is
+returned.
+*/
+#[cfg(any(feature = "graphite", feature = "influxdb"))]
+pub(crate) fn send_lines<S: AsRef<str>>(
+    socket: &UdpSocket,
+    lines: impl IntoIterator<Item = S>,
+) -> io::Result<()> {
+    let mut datagram = String::with_capacity(MAX_DATAGRAM_SIZE);
+    let mut result = Ok(());
+
+    for line in lines {
+        let line = line.as_ref();
+        if !datagram.is_empty() && datagram.len() + line.len() > MAX_DATAGRAM_SIZE {
+            if let Err(e) = socket.send(datagram.as_bytes()) {
+                result = Err(e);
+            }
+            datagram.clear();
+        }
+        datagram.push_str(line);
+    }
+
+    if !datagram.is_empty() {
+        if let Err(e) = socket.send(datagram.as_bytes()) {
+            result = Err(e);
+        }
+    }
+
+    result
+}
@@ -1,20 +1,36 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+use std::mem;
 use std::process::exit;
+use std::ptr;
 use std::time;
 
-use crate::config::PluginConfig;
+use crate::config::{OnError, PluginConfig};
+use crate::interpolation;
+use crate::output::{Notification, Output, Sample};
+use crate::threshold::Thresholds;
+use crate::utils::duration;
+
+/// Smallest difference between the times of two values, as precise as the exec output.
+const MIN_TIME_STEP: time::Duration = time::Duration::from_micros(1);
 
 /// Plugin result for one target/type-instance of the plugin execution.
 pub struct PluginResult<'a> {
     /// The time of measurement
     pub time: time::Duration,
 
-    /// The result value of the plugin
-    pub value: String,
+    /**
+    The result values of the plugin, one for each data source
+    of the type (most types such as gauge only have one).
+    */
+    pub values: Vec<String>,
 
     /**
     The target that was used to compute this result.
     If type_instance below is not specified, this will
-    be used to compute the type_instance. If both target
+    be used to compute the type_instance, through the name
+    given to the target if any. If both target
     and type_instance are None, the type_instance will
     not be set.
     */
@@ -31,35 +47,118 @@ pub struct PluginResult<'a> {
     pub type_instance: Option<String>,
 }
 
+/**
+Error for one target/type-instance of the plugin execution.
+Plugins return it instead of exiting so that a single failing target
+does not take down every other probe. What happens next is decided
+by the on_error policy of the plugin instance.
+*/
+#[derive(Debug)]
+pub struct PluginError<'a> {
+    /// The target for which the plugin failed, if any.
+    pub target: Option<&'a str>,
+
+    /// The type_instance for which the plugin failed, same as in PluginResult.
+    pub type_instance: Option<String>,
+
+    /// Human readable description of the error.
+    pub message: String,
+}
+
+impl<'a> PluginError<'a> {
+    /// Error for a target that is also used as type_instance.
+    pub fn new(target: &'a str, message: String) -> Self {
+        Self { target: Some(target), type_instance: None, message }
+    }
+}
+
+impl fmt::Display for PluginError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Description of a setting of a plugin, shown by the describe command.
+pub struct SettingDesc {
+    /// Key of the setting in the settings table of an instance.
+    pub name: &'static str,
+
+    /// Type of the value, in TOML terms such as "string" or "float".
+    pub r#type: &'static str,
+
+    /// Whether the instances of the plugin must give the setting.
+    pub required: bool,
+
+    pub desc: &'static str,
+}
+
 /**
 Trait that must be implemented by all plugins so they can be executed.
 The idea of a plugin is roughly the same as collectd, although it is slightly more
 coupled to the type of data you want to measure rather than the type of measurement.
 */
-pub trait PluginExecImplementation: Sized {
-    type PluginState: State<Self>;
+pub trait PluginExecImplementation: Sized + Send {
+    type PluginState: State<Self> + Send;
 
-    /// Executed at the start of the program before any execution to check the configuration and eventually initialize stuff.
-    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]);
+    /**
+    Executed before any execution of an instance to check its configuration and eventually
+    initialize stuff. An invalid configuration is reported as an error message.
+    */
+    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) -> Result<(), String>;
 
-    /// Execute an instance of the plugin and return the results for each type-instance.
+    /**
+    Execute an instance of the plugin and return the results for each type-instance.
+    A target that cannot be measured should yield an error instead of a result.
+    */
     fn exec<'a>(
         instance: &str,
         conf: &PluginConfig<Self>,
         state: &mut Self::PluginState,
         targets: &'a [String],
-    ) -> Vec<PluginResult<'a>>;
+    ) -> Vec<Result<PluginResult<'a>, PluginError<'a>>>;
 
     /// Specify the name of the plugin as used in the collectd identifier.
     fn name() -> &'static str;
 
-    /// A way for plugins to provide a description of themselves. Not really used for now but might be in the future.
+    /// A way for plugins to provide a description of themselves, used for instance as help by the Prometheus exporter.
     fn desc() -> &'static str;
+
+    /// The settings the plugin understands, empty if it has none.
+    fn settings() -> &'static [SettingDesc] {
+        &[]
+    }
+
+    /**
+    Whether targets may have settings of their own, which the plugin then reads
+    with PluginConfig::settings_of(). They are rejected otherwise.
+    */
+    fn target_settings() -> bool {
+        false
+    }
+
+    /**
+    Name of the plugin of an instance. This is name() unless the plugin is
+    only known once its instance is configured, such as external plugins.
+    */
+    fn name_of(_conf: &PluginConfig<Self>) -> &'static str {
+        Self::name()
+    }
+
+    /// Description of the plugin of an instance, see name_of().
+    fn desc_of(_conf: &PluginConfig<Self>) -> &'static str {
+        Self::desc()
+    }
 }
 
 /// Each plugin/plugin-instance can have some state associated to it.
 pub trait State<T> {
     fn new(instance: &str, conf: &PluginConfig<T>, targets: &[String]) -> Self;
+
+    /**
+    Save what should survive a restart, called once when the program exits
+    gracefully. Nothing is saved by default.
+    */
+    fn persist(&mut self, _instance: &str, _conf: &PluginConfig<T>, _targets: &[String]) {}
 }
 
 /// Useful for plugins that don't need any particular state.
@@ -84,13 +183,23 @@ where
     config: PluginConfig<T>,
     state: T::PluginState,
     targets: Vec<String>,
+
+    /// Name given to each target if any, used as its type instance.
+    names: Vec<Option<String>>,
+
     instance: String,
+    hostname: String,
+    plugin_name: String,
 
-    interval_str: String,
-    interval_duration: Option<time::Duration>,
-    last: time::Instant,
+    interval_duration: time::Duration,
 
-    putval_base_str: String,
+    on_error: OnError,
+    data_sources: usize,
+
+    thresholds: Option<Thresholds>,
+
+    /// Time of the last value of each type instance, so that it never goes backwards.
+    last_times: HashMap<String, time::Duration>,
 }
 
 impl<T> PluginInstance<T>
@@ -101,60 +210,204 @@ where
         plugin_config: PluginConfig<T>,
         hostname: String,
         instance: String,
-        interval: String,
-    ) -> Self {
-        let mut targets: Vec<String> = match plugin_config.targets.to_owned() {
-            Some(targets) => targets,
-            None => vec![],
+        interval_duration: time::Duration,
+    ) -> Result<Self, String> {
+        let invalid = |message: &str| {
+            Err(format!(
+                "'{}:{}' {}",
+                T::name_of(&plugin_config),
+                instance,
+                message
+            ))
         };
 
-        if plugin_config.target.is_some() {
-            targets.push(plugin_config.target.to_owned().unwrap());
+        if plugin_config.target.is_some() && plugin_config.targets.is_some() {
+            return invalid("cannot have both target and targets");
+        }
+
+        if plugin_config.has_target_settings() && !T::target_settings() {
+            return invalid("targets cannot have settings of their own");
         }
 
-        let (interval_duration, interval_str) = if let Some(interval) = plugin_config.interval {
-            (
-                Some(time::Duration::from_secs_f32(interval)),
-                interval.to_string(),
-            )
-        } else {
-            (None, interval)
+        // shorter intervals than the collectd one are fine, see the interval key
+        let interval_duration = match plugin_config.interval {
+            Some(interval) => match duration::positive(interval) {
+                Some(interval) => interval,
+                None => return invalid("interval must be a positive number of seconds"),
+            },
+            None => interval_duration,
         };
 
-        // we precompute some of the string that we shall print on each execution
-        let plugin_name = if let Some(name) = &plugin_config.name { name } else { T::name() };
-        let type_name = &plugin_config.r#type;
-        let putval_base_str = format!("PUTVAL {hostname}/{plugin_name}-{instance}/{type_name}");
+        let unknown = plugin_config
+            .threshold
+            .as_ref()
+            .and_then(|t| t.unknown.keys().next());
+        if let Some(key) = unknown {
+            return invalid(&format!("unknown field `{}` in threshold", key));
+        }
+
+        let targets: Vec<String> = plugin_config
+            .target_configs()
+            .map(|target| target.target().to_owned())
+            .collect();
+        let names: Vec<Option<String>> = plugin_config
+            .target_names()
+            .into_iter()
+            .map(|name| name.map(str::to_owned))
+            .collect();
+
+        let plugin_name = match &plugin_config.name {
+            Some(name) => name.to_owned(),
+            None => T::name_of(&plugin_config).to_owned(),
+        };
 
-        T::pre(&instance, &plugin_config, &targets);
+        let on_error = plugin_config.on_error.to_owned().unwrap_or(OnError::Skip);
+
+        let data_sources = plugin_config.data_sources.unwrap_or(1);
+        if data_sources == 0 || !targets.len().is_multiple_of(data_sources) {
+            return invalid("the number of targets must be a multiple of data_sources");
+        }
+
+        let thresholds = plugin_config.threshold.to_owned().map(Thresholds::new);
+
+        T::pre(&instance, &plugin_config, &targets)?;
         let state = T::PluginState::new(&instance, &plugin_config, &targets);
 
-        Self {
+        Ok(Self {
             config: plugin_config,
             state,
             targets,
+            names,
             instance,
+            hostname,
+            plugin_name,
             interval_duration,
-            interval_str,
-            putval_base_str,
-            last: time::Instant::now(),
+            on_error,
+            data_sources,
+            thresholds,
+            last_times: HashMap::new(),
+        })
+    }
+
+    /// Assemble the sample handed over to the outputs.
+    fn sample<'a>(
+        &'a self,
+        type_instance: Option<&'a str>,
+        time: time::Duration,
+        values: &'a [String],
+        error: Option<&'a str>,
+    ) -> Sample<'a> {
+        Sample {
+            host: &self.hostname,
+            plugin: &self.plugin_name,
+            plugin_instance: &self.instance,
+            r#type: &self.config.r#type,
+            type_instance,
+            interval: self.interval_duration,
+            time,
+            values,
+            error,
+            implementation: T::name_of(&self.config),
+            desc: T::desc_of(&self.config),
         }
     }
 
-    /// Echo the putval command to stdout.
-    fn putval(&self, type_instance: Option<&str>, time: &str, value: &str) {
-        let putval_base_str = &self.putval_base_str;
-        let interval_str = &self.interval_str;
+    /**
+    Make sure the time of a value is always after the previous one with the same
+    type instance, even if the clock goes backwards or values are measured more
+    than once within its resolution. Otherwise collectd would reject the value.
+    */
+    fn monotonic(&mut self, type_instance: &str, time: time::Duration) -> time::Duration {
+        let time = match self.last_times.get(type_instance) {
+            Some(last) if time <= *last => *last + MIN_TIME_STEP,
+            _ => time,
+        };
+
+        self.last_times.insert(type_instance.to_owned(), time);
+        time
+    }
+
+    /**
+    Emit a single value out of the results of consecutive targets, one for each
+    data source of the type. Failed targets are reported and handled according
+    to the on_error policy of the instance. The value is then checked against
+    the thresholds, if any.
+    */
+    fn emit(
+        &mut self,
+        output: &dyn Output,
+        targets: &[String],
+        results: Vec<Result<PluginResult, PluginError>>,
+    ) {
+        let mut time = None;
+        let mut type_instance: Option<Cow<str>> = None;
+        let mut values: Vec<String> = Vec::with_capacity(results.len());
+        let mut errors: Vec<String> = vec![];
+        let mut skip = false;
+
+        for (index, result) in results.into_iter().enumerate() {
+            let (result_type_instance, target) = match result {
+                Ok(result) => {
+                    time.get_or_insert(result.time);
+                    values.extend(result.values);
+                    (result.type_instance, result.target)
+                }
+                Err(error) => {
+                    let message = interpolation::redact(&error.message);
+                    eprintln!(
+                        "error: '{}:{}' {}",
+                        T::name_of(&self.config),
+                        self.instance,
+                        message
+                    );
+                    match &self.on_error {
+                        OnError::Skip => skip = true,
+                        OnError::Unknown => values.push("U".to_owned()),
+                        OnError::Sentinel(value) => values.push(value.to_string()),
+                        OnError::Abort => exit(1),
+                    }
+                    errors.push(message);
+                    (error.type_instance, error.target)
+                }
+            };
 
-        // FIXME: we should probably abstract that away with a macro
-        match type_instance {
-            Some(type_instance) => {
-                println!(
-                    "{putval_base_str}-\"{type_instance}\" interval={interval_str} {time}:{value}"
-                )
+            // the value is identified by its first result
+            if index == 0 {
+                type_instance = result_type_instance
+                    .map(Cow::Owned)
+                    .or(target.map(|target| {
+                        match target_index(targets, target).and_then(|i| self.names[i].as_ref()) {
+                            Some(name) => Cow::Owned(name.to_owned()),
+                            None => Cow::Borrowed(target),
+                        }
+                    }));
             }
-            None => println!("{putval_base_str} interval={interval_str} {time}:{value}"),
-        };
+        }
+
+        let key = type_instance.as_deref().unwrap_or("");
+        let time = self.monotonic(key, time.unwrap_or_else(now));
+        let error = if errors.is_empty() { None } else { Some(errors.join("; ")) };
+
+        if skip {
+            output.skip(&self.sample(type_instance.as_deref(), time, &[], error.as_deref()));
+            return;
+        }
+
+        output.write(&self.sample(type_instance.as_deref(), time, &values, error.as_deref()));
+
+        let notification = self.thresholds.as_mut().and_then(|t| t.check(key, &values));
+        if let Some((severity, message)) = notification {
+            output.notify(&Notification {
+                severity,
+                host: &self.hostname,
+                plugin: &self.plugin_name,
+                plugin_instance: &self.instance,
+                r#type: &self.config.r#type,
+                type_instance: type_instance.as_deref(),
+                time,
+                message: &message,
+            });
+        }
     }
 }
 
@@ -163,37 +416,75 @@ This is the trait that will actually be used to execute each plugin instance
 in the main loop of the program. Since this will be called on dyn trait objects,
 it will use a vtable indirection on each call. If you have a better idea, feel
 free to propose one.
+
+Plugin instances may be executed concurrently by the worker pool, hence the
+Send requirement.
 */
-pub trait ExecutablePlugin {
-    /// Execute the plugin instance, printing it's value on stdout.
-    fn exec(&mut self);
+pub trait ExecutablePlugin: Send {
+    /// Execute the plugin instance, writing its values to the output.
+    fn exec(&mut self, output: &dyn Output);
+
+    /// Interval at which the scheduler should execute the plugin instance.
+    fn interval(&self) -> time::Duration;
+
+    /// Name of the plugin instance as 'plugin:instance', used in diagnostics.
+    fn name(&self) -> String;
+
+    /// Persist the state of the plugin instance before exiting, see State::persist().
+    fn persist(&mut self);
 }
 
 impl<T, S> ExecutablePlugin for PluginInstance<T>
 where
     T: PluginExecImplementation<PluginState = S> + ToOwned + Clone,
-    S: State<T> + Clone,
+    S: State<T> + Send,
 {
-    fn exec(&mut self) {
-        if let Some(interval) = self.interval_duration {
-            if self.last.elapsed() < interval {
-                return;
+    fn exec(&mut self, output: &dyn Output) {
+        // the results borrow the targets while the instance is updated
+        let targets = mem::take(&mut self.targets);
+        let mut results =
+            T::exec(&self.instance, &self.config, &mut self.state, &targets).into_iter();
+
+        // consecutive results are merged into the data sources of a single value
+        loop {
+            let group: Vec<_> = results.by_ref().take(self.data_sources).collect();
+            if group.is_empty() {
+                break;
             }
-            self.last = time::Instant::now();
+
+            self.emit(output, &targets, group);
         }
 
-        for result in T::exec(&self.instance, &self.config, &mut self.state, &self.targets) {
-            let time = result.time.as_secs().to_string();
+        drop(results);
+        self.targets = targets;
+    }
 
-            if let Some(type_instance) = result.type_instance {
-                self.putval(Some(&type_instance), &time, &result.value);
-            } else {
-                self.putval(result.target, &time, &result.value);
-            }
-        }
+    fn interval(&self) -> time::Duration {
+        self.interval_duration
+    }
+
+    fn name(&self) -> String {
+        format!("{}:{}", T::name_of(&self.config), self.instance)
+    }
+
+    fn persist(&mut self) {
+        self.state
+            .persist(&self.instance, &self.config, &self.targets);
     }
 }
 
+/**
+Index of the target a result refers to. Results borrow their target from the
+targets handed over to exec, which tells apart identical targets; a copy is
+matched by value instead.
+*/
+fn target_index(targets: &[String], target: &str) -> Option<usize> {
+    targets
+        .iter()
+        .position(|t| ptr::eq(t.as_str(), target))
+        .or_else(|| targets.iter().position(|t| t == target))
+}
+
 pub fn now() -> time::Duration {
     time::SystemTime::now()
         .duration_since(time::SystemTime::UNIX_EPOCH)
@@ -202,3 +493,204 @@ pub fn now() -> time::Duration {
             exit(1);
         })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::sync::Mutex;
+
+    /// Measure each target as itself, the targets starting with '!' fail.
+    #[derive(Debug, Clone, Deserialize)]
+    struct Echo {}
+
+    impl PluginExecImplementation for Echo {
+        type PluginState = EmptyState;
+
+        fn pre(
+            _instance: &str,
+            _conf: &PluginConfig<Self>,
+            _targets: &[String],
+        ) -> Result<(), String> {
+            Ok(())
+        }
+
+        fn exec<'a>(
+            _instance: &str,
+            _conf: &PluginConfig<Self>,
+            _state: &mut Self::PluginState,
+            targets: &'a [String],
+        ) -> Vec<Result<PluginResult<'a>, PluginError<'a>>> {
+            targets
+                .iter()
+                .map(|target| match target.strip_prefix('!') {
+                    Some(_) => Err(PluginError::new(target, "failed".to_owned())),
+                    None => Ok(PluginResult {
+                        time: time::Duration::from_secs(1_700_000_000),
+                        values: vec![target.to_owned()],
+                        target: Some(target),
+                        type_instance: None,
+                    }),
+                })
+                .collect()
+        }
+
+        fn name() -> &'static str {
+            "echo"
+        }
+
+        fn desc() -> &'static str {
+            "Measure each target as itself."
+        }
+    }
+
+    /// What the tests look at in a sample.
+    #[derive(Debug, PartialEq)]
+    struct Written {
+        type_instance: Option<String>,
+        values: Vec<String>,
+        time: time::Duration,
+    }
+
+    /// Keep the samples written.
+    #[derive(Default)]
+    struct Recorder {
+        samples: Mutex<Vec<Written>>,
+    }
+
+    impl Output for Recorder {
+        fn write(&self, sample: &Sample) {
+            self.samples.lock().unwrap().push(Written {
+                type_instance: sample.type_instance.map(str::to_owned),
+                values: sample.values.to_vec(),
+                time: sample.time,
+            });
+        }
+    }
+
+    fn instance(config: &str) -> PluginInstance<Echo> {
+        PluginInstance::new(
+            toml::from_str(config).unwrap(),
+            "h".to_owned(),
+            "i".to_owned(),
+            time::Duration::from_secs(10),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn times_only_go_forward_for_each_type_instance() {
+        let mut instance = instance(r#"type = "gauge""#);
+        let time = time::Duration::from_secs(100);
+        let second = time::Duration::from_secs(1);
+
+        assert_eq!(instance.monotonic("a", time), time);
+        assert_eq!(instance.monotonic("a", time), time + MIN_TIME_STEP);
+        assert_eq!(
+            instance.monotonic("a", time - second),
+            time + 2 * MIN_TIME_STEP
+        );
+        assert_eq!(instance.monotonic("b", time - second), time - second);
+        assert_eq!(instance.monotonic("a", time + second), time + second);
+    }
+
+    #[test]
+    fn values_measured_at_the_same_time_are_spread() {
+        let mut instance = instance(
+            r#"
+            type = "gauge"
+            target = "a"
+            "#,
+        );
+        let output = Recorder::default();
+        instance.exec(&output);
+        instance.exec(&output);
+
+        let samples = output.samples.lock().unwrap();
+        let times: Vec<_> = samples.iter().map(|sample| sample.time).collect();
+        assert_eq!(times.len(), 2);
+        assert_eq!(times[1], times[0] + MIN_TIME_STEP);
+    }
+
+    fn written(instance: &mut PluginInstance<Echo>) -> Vec<(Option<String>, Vec<String>)> {
+        let output = Recorder::default();
+        instance.exec(&output);
+
+        let samples = output.samples.into_inner().unwrap();
+        samples
+            .into_iter()
+            .map(|sample| (sample.type_instance, sample.values))
+            .collect()
+    }
+
+    fn strings(strings: &[&str]) -> Vec<String> {
+        strings.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn consecutive_targets_make_the_data_sources_of_a_value() {
+        let mut instance = instance(
+            r#"
+            type = "if_octets"
+            data_sources = 2
+            targets = ["rx0", "tx0", "rx1", "tx1"]
+            "#,
+        );
+
+        assert_eq!(
+            written(&mut instance),
+            [
+                (Some("rx0".to_owned()), strings(&["rx0", "tx0"])),
+                (Some("rx1".to_owned()), strings(&["rx1", "tx1"])),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_data_sources_follow_the_on_error_policy() {
+        let mut skipped = instance(
+            r#"
+            type = "if_octets"
+            data_sources = 2
+            targets = ["rx0", "!tx0", "rx1", "tx1"]
+            "#,
+        );
+        assert_eq!(
+            written(&mut skipped),
+            [(Some("rx1".to_owned()), strings(&["rx1", "tx1"]))]
+        );
+
+        let mut unknown = instance(
+            r#"
+            type = "if_octets"
+            data_sources = 2
+            on_error = "unknown"
+            targets = [{ target = "!rx0", name = "eth0" }, "tx0"]
+            "#,
+        );
+        assert_eq!(
+            written(&mut unknown),
+            [(Some("eth0".to_owned()), strings(&["U", "tx0"]))]
+        );
+    }
+
+    #[test]
+    fn targets_must_fill_the_data_sources() {
+        let config = toml::from_str(
+            r#"
+            type = "if_octets"
+            data_sources = 2
+            targets = ["rx0", "tx0", "rx1"]
+            "#,
+        )
+        .unwrap();
+        let instance = PluginInstance::<Echo>::new(
+            config,
+            "h".to_owned(),
+            "i".to_owned(),
+            time::Duration::from_secs(10),
+        );
+
+        assert!(instance.is_err());
+    }
+}
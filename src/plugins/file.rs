@@ -1,44 +1,51 @@
+#![cfg(feature = "file")]
+
 use serde::Deserialize;
-use std::process::exit;
 
 use crate::config::PluginConfig;
 use crate::plugin;
+use crate::utils::wildcard;
 
 #[derive(Debug, Clone, Deserialize)]
-pub struct Settings {}
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Type instance of the paths matching a pattern, see utils::wildcard.
+    pub type_instance: Option<String>,
+
+    /// Seconds between two expansions of the patterns, only expanded once otherwise.
+    pub rescan: Option<f64>,
+}
+
+impl wildcard::Settings for Settings {
+    fn type_instance(&self) -> Option<&str> {
+        self.type_instance.as_deref()
+    }
+
+    fn rescan(&self) -> Option<f64> {
+        self.rescan
+    }
+}
 
 impl plugin::PluginExecImplementation for Settings {
-    type PluginState = plugin::EmptyState;
+    type PluginState = wildcard::Targets;
+
+    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) -> Result<(), String> {
+        conf.check_target_required(instance, targets)?;
 
-    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) {
-        conf.check_no_setting_required(instance);
-        conf.check_target_required(instance, targets);
+        wildcard::check_config(conf, targets)
+            .map_err(|e| format!("'{}:{}' {}", Self::name(), instance, e))
     }
 
     fn exec<'a>(
         _instance: &str,
         _conf: &PluginConfig<Self>,
-        _state: &mut Self::PluginState,
+        state: &mut Self::PluginState,
         targets: &'a [String],
-    ) -> Vec<plugin::PluginResult<'a>> {
-        let mut results: Vec<plugin::PluginResult> = Vec::with_capacity(targets.len());
-
-        for target in targets {
-            results.push(plugin::PluginResult {
-                time: plugin::now(),
-                value: std::fs::read_to_string(target)
-                    .unwrap_or_else(|_| {
-                        eprintln!("error: cannot read file '{}'", target);
-                        exit(1);
-                    })
-                    .trim()
-                    .to_owned(),
-                target: Some(target),
-                type_instance: None,
-            });
-        }
-
-        results
+    ) -> Vec<Result<plugin::PluginResult<'a>, plugin::PluginError<'a>>> {
+        state.read(targets, |path| match std::fs::read_to_string(path) {
+            Ok(raw) => Ok(raw.trim().to_owned()),
+            Err(e) => Err(format!("cannot read file '{}': {}", path, e)),
+        })
     }
 
     fn name() -> &'static str {
@@ -48,4 +55,21 @@ impl plugin::PluginExecImplementation for Settings {
     fn desc() -> &'static str {
         "Read raw values from a file."
     }
+
+    fn settings() -> &'static [plugin::SettingDesc] {
+        &[
+            plugin::SettingDesc {
+                name: "type_instance",
+                r#type: "string",
+                required: false,
+                desc: "Type instance of the files matching a pattern, $1... being the wildcards.",
+            },
+            plugin::SettingDesc {
+                name: "rescan",
+                r#type: "float",
+                required: false,
+                desc: "Seconds between two expansions of the patterns, only once by default.",
+            },
+        ]
+    }
 }
@@ -1,51 +1,65 @@
+#![cfg(feature = "file_factor")]
+
 use serde::Deserialize;
-use std::process::exit;
 
 use crate::config::PluginConfig;
 use crate::plugin;
+use crate::utils::wildcard;
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
     pub factor: f64,
+
+    /// Type instance of the paths matching a pattern, see utils::wildcard.
+    pub type_instance: Option<String>,
+
+    /// Seconds between two expansions of the patterns, only expanded once otherwise.
+    pub rescan: Option<f64>,
+}
+
+/// Read the integer value of a file and apply the factor.
+fn read(path: &str, factor: f64) -> Result<String, String> {
+    let raw =
+        std::fs::read_to_string(path).map_err(|e| format!("cannot read file '{}': {}", path, e))?;
+    let raw_int: i64 = raw
+        .trim()
+        .parse()
+        .map_err(|_| format!("cannot parse raw value '{}' as integer", raw.trim()))?;
+    let result = (raw_int as f64) * factor;
+
+    Ok(result.to_string())
+}
+
+impl wildcard::Settings for Settings {
+    fn type_instance(&self) -> Option<&str> {
+        self.type_instance.as_deref()
+    }
+
+    fn rescan(&self) -> Option<f64> {
+        self.rescan
+    }
 }
 
 impl plugin::PluginExecImplementation for Settings {
-    type PluginState = plugin::EmptyState;
+    type PluginState = wildcard::Targets;
+
+    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) -> Result<(), String> {
+        conf.check_setting_required(instance)?;
+        conf.check_target_required(instance, targets)?;
 
-    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) {
-        conf.check_setting_required(instance);
-        conf.check_target_required(instance, targets);
+        wildcard::check_config(conf, targets)
+            .map_err(|e| format!("'{}:{}' {}", Self::name(), instance, e))
     }
 
     fn exec<'a>(
         _instance: &str,
         conf: &PluginConfig<Self>,
-        _state: &mut Self::PluginState,
+        state: &mut Self::PluginState,
         targets: &'a [String],
-    ) -> Vec<plugin::PluginResult<'a>> {
-        let mut results: Vec<plugin::PluginResult> = Vec::with_capacity(targets.len());
+    ) -> Vec<Result<plugin::PluginResult<'a>, plugin::PluginError<'a>>> {
         let factor = conf.settings.as_ref().unwrap().factor;
-
-        for target in targets {
-            let raw = std::fs::read_to_string(target).unwrap_or_else(|_| {
-                eprintln!("error: cannot file '{}'", target);
-                exit(1);
-            });
-            let raw_int: i64 = raw.trim().parse().unwrap_or_else(|_| {
-                eprintln!("error: cannot parse raw value '{}' as integer", raw);
-                exit(1);
-            });
-            let result = (raw_int as f64) * factor;
-
-            results.push(plugin::PluginResult {
-                time: plugin::now(),
-                value: result.to_string(),
-                target: Some(target),
-                type_instance: None,
-            });
-        }
-
-        results
+        state.read(targets, |path| read(path, factor))
     }
 
     fn name() -> &'static str {
@@ -60,4 +74,27 @@ impl plugin::PluginExecImplementation for Settings {
         use this plugin with a factor of 0.001.
         "
     }
+
+    fn settings() -> &'static [plugin::SettingDesc] {
+        &[
+            plugin::SettingDesc {
+                name: "factor",
+                r#type: "float",
+                required: true,
+                desc: "Factor applied to the integer read from the file.",
+            },
+            plugin::SettingDesc {
+                name: "type_instance",
+                r#type: "string",
+                required: false,
+                desc: "Type instance of the files matching a pattern, $1... being the wildcards.",
+            },
+            plugin::SettingDesc {
+                name: "rescan",
+                r#type: "float",
+                required: false,
+                desc: "Seconds between two expansions of the patterns, only once by default.",
+            },
+        ]
+    }
 }
@@ -1,3 +1,5 @@
+#![cfg(feature = "http_latency")]
+
 use serde::Deserialize;
 use std::time;
 
@@ -5,8 +7,10 @@ use ureq::Error;
 
 use crate::config::PluginConfig;
 use crate::plugin;
+use crate::utils::{duration, latency};
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
     /// The text that is expected, otherwise returns -2
     pub expect: Option<String>,
@@ -18,31 +22,32 @@ pub struct Settings {
     pub user_agent: Option<String>,
 }
 
+/// HTTP agent and expected response of a target.
 #[derive(Debug, Clone)]
-pub struct State {
+pub struct TargetState {
     agent: ureq::Agent,
     expected: Option<String>,
     timeout: f32,
-    result_fn: fn(ureq::Response, &State, time::Duration) -> String,
+    result_fn: fn(ureq::Response, &TargetState, time::Duration) -> String,
 }
 
-impl plugin::State<Settings> for State {
-    fn new(_instance: &str, conf: &PluginConfig<Settings>, _targets: &[String]) -> Self {
-        let settings = conf.settings.to_owned().unwrap_or(Settings {
-            expect: None,
-            timeout: None,
-            user_agent: None,
-        });
+impl latency::TargetState<Settings> for TargetState {
+    fn new(settings: Option<&Settings>) -> Self {
+        let settings = match settings {
+            Some(settings) => settings.to_owned(),
+            None => Settings { expect: None, timeout: None, user_agent: None },
+        };
 
         let mut timeout_value: f32 = f32::INFINITY;
         let mut builder = ureq::AgentBuilder::new()
             .user_agent(&settings.user_agent.unwrap_or("collectd-shrimp".to_owned()));
         if let Some(timeout) = settings.timeout {
-            builder = builder.timeout(time::Duration::from_secs_f32(timeout));
+            // checked by pre()
+            builder = builder.timeout(duration::positive(f64::from(timeout)).unwrap());
             timeout_value = timeout;
         }
 
-        let result_fn: fn(ureq::Response, &State, time::Duration) -> String =
+        let result_fn: fn(ureq::Response, &TargetState, time::Duration) -> String =
             if settings.expect.is_some() {
                 |response, state, duration| {
                     if let Ok(response_str) = response.into_string() {
@@ -69,10 +74,12 @@ impl plugin::State<Settings> for State {
 }
 
 impl plugin::PluginExecImplementation for Settings {
-    type PluginState = State;
+    type PluginState = latency::State<TargetState>;
+
+    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) -> Result<(), String> {
+        conf.check_target_required(instance, targets)?;
 
-    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) {
-        conf.check_target_required(instance, targets);
+        latency::check_timeouts(instance, conf, targets, |s| s.timeout)
     }
 
     fn exec<'a>(
@@ -80,10 +87,11 @@ impl plugin::PluginExecImplementation for Settings {
         _conf: &PluginConfig<Self>,
         state: &mut Self::PluginState,
         targets: &'a [String],
-    ) -> Vec<plugin::PluginResult<'a>> {
-        let mut results: Vec<plugin::PluginResult> = Vec::with_capacity(targets.len());
+    ) -> Vec<Result<plugin::PluginResult<'a>, plugin::PluginError<'a>>> {
+        let mut results: Vec<Result<plugin::PluginResult, plugin::PluginError>> =
+            Vec::with_capacity(targets.len());
 
-        for target in targets {
+        for (target, state) in state.targets(targets) {
             // fetch the target
             let measurement_time = plugin::now();
             let start = time::Instant::now();
@@ -103,12 +111,12 @@ impl plugin::PluginExecImplementation for Settings {
                 }
             };
 
-            results.push(plugin::PluginResult {
+            results.push(Ok(plugin::PluginResult {
                 time: measurement_time,
-                value: result,
+                values: vec![result],
                 target: Some(target),
                 type_instance: None,
-            });
+            }));
         }
 
         results
@@ -133,4 +141,31 @@ impl plugin::PluginExecImplementation for Settings {
           * -xxx: Error status code such as 404, 503, ...
         "
     }
+
+    fn target_settings() -> bool {
+        true
+    }
+
+    fn settings() -> &'static [plugin::SettingDesc] {
+        &[
+            plugin::SettingDesc {
+                name: "expect",
+                r#type: "string",
+                required: false,
+                desc: "Expected response body once trimmed, otherwise the value is -2.",
+            },
+            plugin::SettingDesc {
+                name: "timeout",
+                r#type: "float",
+                required: false,
+                desc: "Maximum time of the query in seconds, which is the value on timeout.",
+            },
+            plugin::SettingDesc {
+                name: "user_agent",
+                r#type: "string",
+                required: false,
+                desc: "User agent of the query, defaults to collectd-shrimp.",
+            },
+        ]
+    }
 }
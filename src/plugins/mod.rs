@@ -1,22 +1,8 @@
-pub mod null;
-
-#[cfg(feature = "sysctl")]
-pub mod sysctl;
-
-#[cfg(feature = "sysctl_factor")]
-pub mod sysctl_factor;
-
-#[cfg(all(target_os = "freebsd", feature = "sysctl_temp"))]
-pub mod sysctl_temp;
-
-#[cfg(feature = "file")]
-pub mod file;
-
-#[cfg(feature = "file_factor")]
-pub mod file_factor;
-
-#[cfg(feature = "http_latency")]
-pub mod http_latency;
-
-#[cfg(feature = "telnet_latency")]
-pub mod telnet_latency;
\ No newline at end of file
+/*!
+The plugins, one module per file in this directory.
+Modules and their registration are generated by the build script, so adding a
+plugin only requires to add its file, gated by a #![cfg(feature = "...")] inner
+attribute on its first line, and its feature in Cargo.toml.
+*/
+
+include!(concat!(env!("OUT_DIR"), "/plugins.rs"));
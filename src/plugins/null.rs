@@ -4,14 +4,15 @@ use crate::config::PluginConfig;
 use crate::plugin;
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {}
 
 impl plugin::PluginExecImplementation for Settings {
     type PluginState = plugin::EmptyState;
 
-    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) {
-        conf.check_no_setting_required(instance);
-        conf.check_no_target_required(instance, targets);
+    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) -> Result<(), String> {
+        conf.check_no_setting_required(instance)?;
+        conf.check_no_target_required(instance, targets)
     }
 
     fn exec<'a>(
@@ -19,13 +20,13 @@ impl plugin::PluginExecImplementation for Settings {
         _conf: &PluginConfig<Self>,
         _state: &mut Self::PluginState,
         _targets: &'a [String],
-    ) -> Vec<plugin::PluginResult<'a>> {
-        vec![plugin::PluginResult {
+    ) -> Vec<Result<plugin::PluginResult<'a>, plugin::PluginError<'a>>> {
+        vec![Ok(plugin::PluginResult {
             time: plugin::now(),
-            value: "0".to_owned(),
+            values: vec!["0".to_owned()],
             target: None,
             type_instance: None,
-        }]
+        })]
     }
 
     fn name() -> &'static str {
@@ -1,19 +1,21 @@
+#![cfg(feature = "sysctl")]
+
 use serde::Deserialize;
-use std::process::exit;
 
 use crate::config::PluginConfig;
 use crate::plugin;
 use crate::utils;
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {}
 
 impl plugin::PluginExecImplementation for Settings {
     type PluginState = plugin::EmptyState;
 
-    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) {
-        conf.check_no_setting_required(instance);
-        conf.check_target_required(instance, targets);
+    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) -> Result<(), String> {
+        conf.check_no_setting_required(instance)?;
+        conf.check_target_required(instance, targets)
     }
 
     fn exec<'a>(
@@ -21,19 +23,25 @@ impl plugin::PluginExecImplementation for Settings {
         _conf: &PluginConfig<Self>,
         _state: &mut Self::PluginState,
         targets: &'a [String],
-    ) -> Vec<plugin::PluginResult<'a>> {
-        let mut results: Vec<plugin::PluginResult> = Vec::with_capacity(targets.len());
+    ) -> Vec<Result<plugin::PluginResult<'a>, plugin::PluginError<'a>>> {
+        let mut results: Vec<Result<plugin::PluginResult, plugin::PluginError>> =
+            Vec::with_capacity(targets.len());
 
         for target in targets {
-            results.push(plugin::PluginResult {
-                time: plugin::now(),
-                value: utils::sysctl::get_string(target).unwrap_or_else(|_| {
-                    eprintln!("error: cannot read sysctl key '{}'", target);
-                    exit(1);
+            let result = match utils::sysctl::get_string(target) {
+                Ok(value) => Ok(plugin::PluginResult {
+                    time: plugin::now(),
+                    values: vec![value],
+                    target: Some(target),
+                    type_instance: None,
                 }),
-                target: Some(target),
-                type_instance: None,
-            });
+                Err(e) => Err(plugin::PluginError::new(
+                    target,
+                    format!("cannot read sysctl key '{}': {}", target, e),
+                )),
+            };
+
+            results.push(result);
         }
 
         results
@@ -1,21 +1,44 @@
+#![cfg(feature = "sysctl_factor")]
+
 use serde::Deserialize;
-use std::process::exit;
 
 use crate::config::PluginConfig;
 use crate::plugin;
 use crate::utils;
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
     pub factor: f64,
 }
 
+/// Read the integer value of a sysctl key and apply the factor.
+fn read(target: &str, factor: f64) -> Result<plugin::PluginResult<'_>, plugin::PluginError<'_>> {
+    let raw = utils::sysctl::get_string(target).map_err(|e| {
+        plugin::PluginError::new(target, format!("cannot read sysctl key '{}': {}", target, e))
+    })?;
+    let raw_int: i64 = raw.parse().map_err(|_| {
+        plugin::PluginError::new(
+            target,
+            format!("cannot parse sysctl key '{}' value '{}' as integer", target, raw),
+        )
+    })?;
+    let result = (raw_int as f64) * factor;
+
+    Ok(plugin::PluginResult {
+        time: plugin::now(),
+        values: vec![result.to_string()],
+        target: Some(target),
+        type_instance: None,
+    })
+}
+
 impl plugin::PluginExecImplementation for Settings {
     type PluginState = plugin::EmptyState;
 
-    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) {
-        conf.check_setting_required(instance);
-        conf.check_target_required(instance, targets);
+    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) -> Result<(), String> {
+        conf.check_setting_required(instance)?;
+        conf.check_target_required(instance, targets)
     }
 
     fn exec<'a>(
@@ -23,27 +46,13 @@ impl plugin::PluginExecImplementation for Settings {
         conf: &PluginConfig<Self>,
         _state: &mut Self::PluginState,
         targets: &'a [String],
-    ) -> Vec<plugin::PluginResult<'a>> {
-        let mut results: Vec<plugin::PluginResult> = Vec::with_capacity(targets.len());
+    ) -> Vec<Result<plugin::PluginResult<'a>, plugin::PluginError<'a>>> {
+        let mut results: Vec<Result<plugin::PluginResult, plugin::PluginError>> =
+            Vec::with_capacity(targets.len());
         let factor = conf.settings.as_ref().unwrap().factor;
 
         for target in targets {
-            let raw = utils::sysctl::get_string(target).unwrap_or_else(|_| {
-                eprintln!("error: cannot read sysctl key '{}'", target);
-                exit(1);
-            });
-            let raw_int: i64 = raw.parse().unwrap_or_else(|_| {
-                eprintln!("error: cannot parse sysctl key '{}' as integer", raw);
-                exit(1);
-            });
-            let result = (raw_int as f64) * factor;
-
-            results.push(plugin::PluginResult {
-                time: plugin::now(),
-                value: result.to_string(),
-                target: Some(target),
-                type_instance: None,
-            });
+            results.push(read(target, factor));
         }
 
         results
@@ -64,4 +73,13 @@ impl plugin::PluginExecImplementation for Settings {
         You can use a factor of 4096 (page size) to get amount of memory in bytes.
         "
     }
+
+    fn settings() -> &'static [plugin::SettingDesc] {
+        &[plugin::SettingDesc {
+            name: "factor",
+            r#type: "float",
+            required: true,
+            desc: "Factor applied to the integer read from sysctl.",
+        }]
+    }
 }
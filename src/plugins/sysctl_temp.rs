@@ -1,5 +1,6 @@
+#![cfg(all(target_os = "freebsd", feature = "sysctl_temp"))]
+
 use serde::Deserialize;
-use std::process::exit;
 
 use crate::config::PluginConfig;
 use crate::plugin;
@@ -13,6 +14,7 @@ pub enum TemperatureScale {
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
     pub scale: Option<TemperatureScale>,
     pub precision: Option<usize>,
@@ -73,11 +75,38 @@ impl plugin::State<Settings> for State {
     }
 }
 
+/// Read a temperature from a sysctl key and scale it according to the state.
+fn read<'a>(
+    target: &'a str,
+    state: &State,
+) -> Result<plugin::PluginResult<'a>, plugin::PluginError<'a>> {
+    let raw = utils::sysctl::get(target).map_err(|e| {
+        plugin::PluginError::new(target, format!("cannot read sysctl key '{}': {}", target, e))
+    })?;
+    let temp = raw.as_temperature().ok_or_else(|| {
+        plugin::PluginError::new(
+            target,
+            format!(
+                "cannot parse key '{}' with value '{}' as a temperature",
+                target, raw
+            ),
+        )
+    })?;
+    let temp_value = (state.scale_fn)(temp); // we scale the temperature according to what has been stored in state
+
+    Ok(plugin::PluginResult {
+        time: plugin::now(),
+        values: vec![(state.format_fn)(temp_value)],
+        target: Some(target),
+        type_instance: None,
+    })
+}
+
 impl plugin::PluginExecImplementation for Settings {
     type PluginState = State;
 
-    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) {
-        conf.check_target_required(instance, targets);
+    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) -> Result<(), String> {
+        conf.check_target_required(instance, targets)
     }
 
     fn exec<'a>(
@@ -85,29 +114,12 @@ impl plugin::PluginExecImplementation for Settings {
         _conf: &PluginConfig<Self>,
         state: &mut Self::PluginState,
         targets: &'a [String],
-    ) -> Vec<plugin::PluginResult<'a>> {
-        let mut results: Vec<plugin::PluginResult> = Vec::with_capacity(targets.len());
+    ) -> Vec<Result<plugin::PluginResult<'a>, plugin::PluginError<'a>>> {
+        let mut results: Vec<Result<plugin::PluginResult, plugin::PluginError>> =
+            Vec::with_capacity(targets.len());
 
         for target in targets {
-            let raw = utils::sysctl::get(target).unwrap_or_else(|_| {
-                eprintln!("error: cannot read sysctl key '{}'", target);
-                exit(1);
-            });
-            let temp = raw.as_temperature().unwrap_or_else(|| {
-                eprintln!(
-                    "error: cannot parse key '{}' with value '{}' as a temperature",
-                    target, raw
-                );
-                exit(1);
-            });
-            let temp_value = (state.scale_fn)(temp); // we scale the temperature according to what has been stored in state
-
-            results.push(plugin::PluginResult {
-                time: plugin::now(),
-                value: (state.format_fn)(temp_value),
-                target: Some(target),
-                type_instance: None,
-            });
+            results.push(read(target, state));
         }
 
         results
@@ -123,4 +135,21 @@ impl plugin::PluginExecImplementation for Settings {
         If it is not possible to parse the sysctl as a temperature, the plugin fails.
         "
     }
+
+    fn settings() -> &'static [plugin::SettingDesc] {
+        &[
+            plugin::SettingDesc {
+                name: "scale",
+                r#type: "string",
+                required: false,
+                desc: "Kelvin, Celsius or Fahrenheit, defaults to Celsius.",
+            },
+            plugin::SettingDesc {
+                name: "precision",
+                r#type: "integer",
+                required: false,
+                desc: "Number of decimals of the value, up to 8.",
+            },
+        ]
+    }
 }
@@ -1,3 +1,5 @@
+#![cfg(feature = "telnet_latency")]
+
 use std::error::Error;
 use std::time;
 use std::net::TcpStream;
@@ -7,10 +9,12 @@ use serde::Deserialize;
 
 use crate::config::PluginConfig;
 use crate::plugin;
+use crate::utils::{duration, latency};
 
 type ReadFn = fn(TcpStream, Option<&str>) -> Result<bool, Box<dyn Error>>;
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
     /// If specified, this text is sent before expecting a response.
     pub query: Option<String>,
@@ -22,30 +26,36 @@ pub struct Settings {
     pub timeout: Option<f32>,
 }
 
+/// Query sent to a target along with its expected response.
 #[derive(Debug, Clone)]
-pub struct State {
+pub struct TargetState {
     timeout_duration: Duration,
     read_fn: ReadFn,
+    query: Option<String>,
+    expect: Option<String>,
 }
 
-impl plugin::State<Settings> for State {
-    fn new(_instance: &str, conf: &PluginConfig<Settings>, _targets: &[String]) -> Self {
-        let settings = conf.settings.to_owned().unwrap_or(Settings {
-            query: None,
-            expect: None,
-            timeout: None,
-        });
+impl latency::TargetState<Settings> for TargetState {
+    fn new(settings: Option<&Settings>) -> Self {
+        let settings = match settings {
+            Some(settings) => settings.to_owned(),
+            None => Settings { query: None, expect: None, timeout: None },
+        };
 
         // default timeout cannot be infinity, so we set it up to a large enough value
         let timeout_value: f32 = settings.timeout.unwrap_or(600.0);
+        // checked by pre()
+        let timeout_duration = duration::positive(f64::from(timeout_value)).unwrap();
         let read_fn = match settings.expect {
             Some(_) => read_expected,
             None => read_onebyte
         };
 
         Self {
-            timeout_duration: Duration::from_secs_f32(timeout_value),
-            read_fn
+            timeout_duration,
+            read_fn,
+            query: settings.query,
+            expect: settings.expect,
         }
     }
 }
@@ -56,7 +66,7 @@ Returns true if both string match.
 */
 fn read_expected(mut stream: TcpStream, expected: Option<&str>) -> Result<bool, Box<dyn Error>> {
     let expected = expected.unwrap();
-    let mut buf = vec!(0u8; expected.as_bytes().len());
+    let mut buf = vec!(0u8; expected.len());
 
     stream.read_exact(&mut buf)?;
 
@@ -96,29 +106,32 @@ fn query_tcp(target: &str,
 }
 
 impl plugin::PluginExecImplementation for Settings {
-    type PluginState = State;
+    type PluginState = latency::State<TargetState>;
 
-    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) {
-        conf.check_target_required(instance, targets);
+    fn pre(instance: &str, conf: &PluginConfig<Self>, targets: &[String]) -> Result<(), String> {
+        conf.check_target_required(instance, targets)?;
+
+        latency::check_timeouts(instance, conf, targets, |s| s.timeout)
     }
 
     fn exec<'a>(
         _instance: &str,
-        conf: &PluginConfig<Self>,
+        _conf: &PluginConfig<Self>,
         state: &mut Self::PluginState,
         targets: &'a [String],
-    ) -> Vec<plugin::PluginResult<'a>> {
-        let mut results: Vec<plugin::PluginResult> = Vec::with_capacity(targets.len());
+    ) -> Vec<Result<plugin::PluginResult<'a>, plugin::PluginError<'a>>> {
+        let mut results: Vec<Result<plugin::PluginResult, plugin::PluginError>> =
+            Vec::with_capacity(targets.len());
 
-        for target in targets {
+        for (target, state) in state.targets(targets) {
             let measurement_time = plugin::now();
             let start = time::Instant::now();
             let query_response = query_tcp(
                 target,
                 state.timeout_duration,
                 state.read_fn,
-                conf.settings.as_ref().and_then(|s| s.query.as_deref()),
-                conf.settings.as_ref().and_then(|s| s.expect.as_deref())
+                state.query.as_deref(),
+                state.expect.as_deref()
             );
 
             let value = match query_response {
@@ -130,12 +143,12 @@ impl plugin::PluginExecImplementation for Settings {
                 Err(_) => "-1".to_string()
             };
 
-            results.push(plugin::PluginResult {
+            results.push(Ok(plugin::PluginResult {
                 time: measurement_time,
-                value,
+                values: vec![value],
                 target: Some(target),
                 type_instance: None,
-            });
+            }));
         }
 
         results
@@ -160,4 +173,31 @@ impl plugin::PluginExecImplementation for Settings {
           * -2: (if configured) The value is different from the configured expected value.
         "
     }
+
+    fn target_settings() -> bool {
+        true
+    }
+
+    fn settings() -> &'static [plugin::SettingDesc] {
+        &[
+            plugin::SettingDesc {
+                name: "query",
+                r#type: "string",
+                required: false,
+                desc: "Text sent once connected, before reading the response.",
+            },
+            plugin::SettingDesc {
+                name: "expect",
+                r#type: "string",
+                required: false,
+                desc: "Expected beginning of the response, otherwise the value is -2.",
+            },
+            plugin::SettingDesc {
+                name: "timeout",
+                r#type: "float",
+                required: false,
+                desc: "Maximum time of the query in seconds, defaults to 600.",
+            },
+        ]
+    }
 }
\ No newline at end of file
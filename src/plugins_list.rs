@@ -1,146 +1,295 @@
-use std::process::exit;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::time;
 
-use crate::config::Config;
+use crate::config::{self, Location, PluginConfig};
 use crate::plugin;
-use crate::plugins;
+
+/// Instance of a plugin, as created by a loader.
+type Instance = Result<Box<dyn plugin::ExecutablePlugin>, Box<dyn Error>>;
 
 /**
-Load all the plugins found in the configuration file
-and return them as a vector of executable instances.
+Create an instance of a plugin out of its name and its table in the plugin
+section of the configuration, given the hostname and the default interval.
 */
-pub fn load_plugins(
-    config: Config,
-    hostname: &str,
-    interval: &str,
-) -> Vec<Box<dyn plugin::ExecutablePlugin>> {
-    let mut plugins: Vec<Box<dyn plugin::ExecutablePlugin>> = vec![];
-
-    // FIXME: refactor that with macros
-
-    // null plugin
-    if let Some(plugin) = config.null {
-        for (instance_name, instance_config) in &plugin {
-            let plugin_instance: plugin::PluginInstance<plugins::null::Settings> =
-                plugin::PluginInstance::new(
-                    instance_config.to_owned(),
-                    hostname.to_owned(),
-                    instance_name.to_owned(),
-                    interval.to_owned(),
-                );
-
-            plugins.push(Box::new(plugin_instance));
-        }
+pub type Loader = Box<dyn Fn(&str, toml::Value, &str, time::Duration) -> Instance + Send + Sync>;
+
+/**
+Entry of the plugin registry.
+The registry of the built-in plugins is generated by the build script out of
+the files in src/plugins, see plugins::registry(). Other plugins can be added
+to it with Registration::of().
+*/
+pub struct Registration {
+    /// Name of the plugin, which is also the name of its configuration section.
+    pub name: &'static str,
+
+    pub desc: &'static str,
+
+    /// Settings understood by the plugin, see describe().
+    pub settings: &'static [plugin::SettingDesc],
+
+    load: Loader,
+}
+
+impl Registration {
+    /// Registration of the plugin implemented by T.
+    pub fn of<T>() -> Self
+    where
+        T: plugin::PluginExecImplementation
+            + ToOwned
+            + Clone
+            + serde::de::DeserializeOwned
+            + 'static,
+    {
+        Self::new(T::name(), T::desc(), T::settings(), Box::new(load::<T>))
     }
 
-    #[cfg(feature = "sysctl")]
-    if let Some(plugin) = config.sysctl {
-        for (instance_name, instance_config) in &plugin {
-            let plugin_instance: plugin::PluginInstance<plugins::sysctl::Settings> =
-                plugin::PluginInstance::new(
-                    instance_config.to_owned(),
-                    hostname.to_owned(),
-                    instance_name.to_owned(),
-                    interval.to_owned(),
-                );
-
-            plugins.push(Box::new(plugin_instance));
-        }
+    /// Registration of a plugin that is not implemented by a type, such as external plugins.
+    pub fn new(
+        name: &'static str,
+        desc: &'static str,
+        settings: &'static [plugin::SettingDesc],
+        load: Loader,
+    ) -> Self {
+        Self { name, desc, settings, load }
     }
+}
 
-    #[cfg(feature = "sysctl_factor")]
-    if let Some(plugin) = config.sysctl_factor {
-        for (instance_name, instance_config) in &plugin {
-            let plugin_instance: plugin::PluginInstance<plugins::sysctl_factor::Settings> =
-                plugin::PluginInstance::new(
-                    instance_config.to_owned(),
-                    hostname.to_owned(),
-                    instance_name.to_owned(),
-                    interval.to_owned(),
-                );
-
-            plugins.push(Box::new(plugin_instance));
-        }
+/// Parse the configuration of an instance and create it.
+fn load<T>(
+    instance: &str,
+    config: toml::Value,
+    hostname: &str,
+    interval_duration: time::Duration,
+) -> Instance
+where
+    T: plugin::PluginExecImplementation + ToOwned + Clone + serde::de::DeserializeOwned + 'static,
+{
+    let config: PluginConfig<T> = config
+        .try_into()
+        .map_err(|e| format!("'{}:{}' {}", T::name(), instance, config::one_line(&e)))?;
+
+    let plugin_instance: plugin::PluginInstance<T> = plugin::PluginInstance::new(
+        config,
+        hostname.to_owned(),
+        instance.to_owned(),
+        interval_duration,
+    )?;
+
+    Ok(Box::new(plugin_instance))
+}
+
+/// Print the name and description of the plugins compiled in.
+pub fn list_plugins(registry: &[Registration]) {
+    for registration in registry {
+        let desc = registration
+            .desc
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+        println!("{}\n    {}\n", registration.name, desc);
     }
+}
 
-    #[cfg(all(target_os = "freebsd", feature = "sysctl_temp"))]
-    if let Some(plugin) = config.sysctl_temp {
-        for (instance_name, instance_config) in &plugin {
-            let plugin_instance: plugin::PluginInstance<plugins::sysctl_temp::Settings> =
-                plugin::PluginInstance::new(
-                    instance_config.to_owned(),
-                    hostname.to_owned(),
-                    instance_name.to_owned(),
-                    interval.to_owned(),
-                );
-
-            plugins.push(Box::new(plugin_instance));
-        }
+/// Print the description of a plugin along with the keys of its instances.
+pub fn describe(registry: &[Registration], name: &str) -> Result<(), String> {
+    let registration = registry
+        .iter()
+        .find(|r| r.name == name)
+        .ok_or_else(|| format!("unknown plugin '{}' (see list-plugins)", name))?;
+
+    let desc = registration
+        .desc
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    println!("{}\n    {}\n", registration.name, desc);
+
+    println!("instance keys:");
+    print_settings(INSTANCE_KEYS);
+
+    println!("\nsettings:");
+    if registration.settings.is_empty() {
+        println!("    none");
     }
+    print_settings(registration.settings);
 
-    #[cfg(feature = "file")]
-    if let Some(plugin) = config.file {
-        for (instance_name, instance_config) in &plugin {
-            let plugin_instance: plugin::PluginInstance<plugins::file::Settings> =
-                plugin::PluginInstance::new(
-                    instance_config.to_owned(),
-                    hostname.to_owned(),
-                    instance_name.to_owned(),
-                    interval.to_owned(),
-                );
-
-            plugins.push(Box::new(plugin_instance));
-        }
+    Ok(())
+}
+
+fn print_settings(settings: &[plugin::SettingDesc]) {
+    for setting in settings {
+        let required = if setting.required { " (required)" } else { "" };
+        println!(
+            "    {:<14} {:<8} {}{}",
+            setting.name, setting.r#type, setting.desc, required
+        );
     }
+}
 
-    #[cfg(feature = "file_factor")]
-    if let Some(plugin) = config.file_factor {
-        for (instance_name, instance_config) in &plugin {
-            let plugin_instance: plugin::PluginInstance<plugins::file_factor::Settings> =
-                plugin::PluginInstance::new(
-                    instance_config.to_owned(),
-                    hostname.to_owned(),
-                    instance_name.to_owned(),
-                    interval.to_owned(),
-                );
-
-            plugins.push(Box::new(plugin_instance));
-        }
+/// Keys of the instance tables common to all the plugins, see config::PluginConfig.
+const INSTANCE_KEYS: &[plugin::SettingDesc] = &[
+    plugin::SettingDesc {
+        name: "type",
+        r#type: "string",
+        required: true,
+        desc: "Type of the values, as found in types.db.",
+    },
+    plugin::SettingDesc {
+        name: "name",
+        r#type: "string",
+        required: false,
+        desc: "Name of the plugin in the identifier, defaults to the plugin.",
+    },
+    plugin::SettingDesc {
+        name: "interval",
+        r#type: "float",
+        required: false,
+        desc: "Seconds between executions, defaults to the collectd interval.",
+    },
+    plugin::SettingDesc {
+        name: "on_error",
+        r#type: "string",
+        required: false,
+        desc: "skip, unknown, { sentinel = <value> } or abort.",
+    },
+    plugin::SettingDesc {
+        name: "target",
+        r#type: "string",
+        required: false,
+        desc: "Target to measure, if there is only one, or a table as in targets.",
+    },
+    plugin::SettingDesc {
+        name: "targets",
+        r#type: "array",
+        required: false,
+        desc: "Targets to measure, a { target, name, settings } table overriding them.",
+    },
+    plugin::SettingDesc {
+        name: "data_sources",
+        r#type: "integer",
+        required: false,
+        desc: "Number of consecutive targets merged into a value.",
+    },
+    plugin::SettingDesc {
+        name: "threshold",
+        r#type: "table",
+        required: false,
+        desc: "Limits over which notifications are emitted.",
+    },
+    plugin::SettingDesc {
+        name: "settings",
+        r#type: "table",
+        required: false,
+        desc: "Settings of the plugin, see below.",
+    },
+];
+
+/// A plugin instance found in the configuration.
+pub(crate) struct Loaded {
+    /// Name of the instance as 'plugin:instance'.
+    pub name: String,
+
+    /// The table of the instance in the configuration.
+    pub config: toml::Value,
+
+    /// The instance, None if it was left unchanged.
+    pub instance: Option<Box<dyn plugin::ExecutablePlugin>>,
+}
+
+/// The registered plugins, along with what their instances need to be created.
+pub(crate) struct Plugins {
+    registry: Vec<Registration>,
+    hostname: String,
+    interval_duration: time::Duration,
+}
+
+impl Plugins {
+    pub fn new(
+        registry: Vec<Registration>,
+        hostname: String,
+        interval_duration: time::Duration,
+    ) -> Self {
+        Self { registry, hostname, interval_duration }
     }
 
-    #[cfg(feature = "http_latency")]
-    if let Some(plugin) = config.http_latency {
-        for (instance_name, instance_config) in &plugin {
-            let plugin_instance: plugin::PluginInstance<plugins::http_latency::Settings> =
-                plugin::PluginInstance::new(
-                    instance_config.to_owned(),
-                    hostname.to_owned(),
-                    instance_name.to_owned(),
-                    interval.to_owned(),
-                );
-
-            plugins.push(Box::new(plugin_instance));
+    /**
+    Load all the plugin instances found in the plugin sections of the configuration.
+    The instances for which unchanged() is true are not created again, this is used
+    to keep the state of the instances whose configuration did not change on reload.
+    Every instance is loaded even if some fail, so that all the problems are reported,
+    along with where they are in the configuration file.
+    */
+    pub fn load(
+        &self,
+        sections: BTreeMap<String, toml::Value>,
+        locations: &HashMap<String, Location>,
+        unchanged: impl Fn(&str, &toml::Value) -> bool,
+    ) -> Result<Vec<Loaded>, Vec<String>> {
+        let mut plugins: Vec<Loaded> = vec![];
+        let mut errors: Vec<String> = vec![];
+
+        for (plugin_name, section) in sections {
+            let registration = match self.registry.iter().find(|r| r.name == plugin_name) {
+                Some(registration) => registration,
+                None => {
+                    errors.push(config::located(
+                        locations,
+                        &plugin_name,
+                        format!(
+                            "unknown plugin '{}', it may not have been compiled in (see list-plugins)",
+                            plugin_name
+                        ),
+                    ));
+                    continue;
+                }
+            };
+
+            let instances = match section {
+                toml::Value::Table(instances) => instances,
+                _ => {
+                    errors.push(config::located(
+                        locations,
+                        &plugin_name,
+                        format!("'{}' must be a table of instances", plugin_name),
+                    ));
+                    continue;
+                }
+            };
+
+            for (instance_name, config) in instances {
+                let name = format!("{}:{}", plugin_name, instance_name);
+
+                let instance = if unchanged(&name, &config) {
+                    None
+                } else {
+                    match (registration.load)(
+                        &instance_name,
+                        config.to_owned(),
+                        &self.hostname,
+                        self.interval_duration,
+                    ) {
+                        Ok(instance) => Some(instance),
+                        Err(e) => {
+                            errors.push(config::located(locations, &name, e.to_string()));
+                            continue;
+                        }
+                    }
+                };
+
+                plugins.push(Loaded { name, config, instance });
+            }
         }
-    }
 
-    #[cfg(feature = "telnet_latency")]
-    if let Some(plugin) = config.telnet_latency {
-        for (instance_name, instance_config) in &plugin {
-            let plugin_instance: plugin::PluginInstance<plugins::telnet_latency::Settings> =
-                plugin::PluginInstance::new(
-                    instance_config.to_owned(),
-                    hostname.to_owned(),
-                    instance_name.to_owned(),
-                    interval.to_owned(),
-                );
-
-            plugins.push(Box::new(plugin_instance));
+        if !errors.is_empty() {
+            return Err(errors);
         }
-    }
 
-    if plugins.is_empty() {
-        eprintln!("warning: no plugin configured");
-        exit(1);
-    }
+        if plugins.is_empty() {
+            return Err(vec!["no plugin configured".to_owned()]);
+        }
 
-    plugins
+        Ok(plugins)
+    }
 }
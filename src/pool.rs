@@ -0,0 +1,67 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::output::Output;
+use crate::plugin::ExecutablePlugin;
+use crate::scheduler::Event;
+
+/// A plugin instance along with the id of its slot in the scheduler.
+type Job = (u64, Box<dyn ExecutablePlugin>);
+
+/**
+Pool of worker threads executing plugin instances.
+
+Each worker pulls the next instance to execute from a shared queue, so that a
+single slow instance (say an HTTP probe waiting for its timeout) only keeps one
+worker busy while the others go on with the remaining instances.
+Executed instances are handed back to the scheduler as events. An instance that
+panics is dropped instead, since its state may be left inconsistent, and only
+its name is handed back so that the failure is reported.
+*/
+pub struct Pool {
+    jobs: mpsc::Sender<Job>,
+}
+
+impl Pool {
+    /// Spawn `concurrency` worker threads writing to the given output.
+    pub fn new(concurrency: usize, output: Arc<dyn Output>, events: mpsc::Sender<Event>) -> Self {
+        let (jobs_tx, jobs_rx) = mpsc::channel::<Job>();
+        let jobs_rx = Arc::new(Mutex::new(jobs_rx));
+
+        for _ in 0..concurrency {
+            let jobs_rx = Arc::clone(&jobs_rx);
+            let events = events.clone();
+            let output = Arc::clone(&output);
+
+            thread::spawn(move || loop {
+                // the lock is released as soon as we got the next instance
+                let job = jobs_rx.lock().unwrap().recv();
+
+                match job {
+                    Ok((id, mut instance)) => {
+                        let executed = panic::catch_unwind(AssertUnwindSafe(|| {
+                            instance.exec(output.as_ref())
+                        }));
+                        let event = match executed {
+                            Ok(()) => Event::Done(id, instance),
+                            Err(_) => Event::Failed(id, instance.name()),
+                        };
+                        if events.send(event).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break, // the pool was dropped
+                }
+            });
+        }
+
+        Self { jobs: jobs_tx }
+    }
+
+    /// Queue an instance for execution.
+    pub fn submit(&self, id: u64, instance: Box<dyn ExecutablePlugin>) {
+        self.jobs.send((id, instance)).unwrap();
+    }
+}
@@ -0,0 +1,420 @@
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::mem;
+use std::path::PathBuf;
+use std::process::exit;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time;
+
+use crate::config;
+use crate::interpolation;
+use crate::output::Output;
+use crate::plugin::{self, ExecutablePlugin};
+use crate::plugins_list::{Loaded, Plugins};
+use crate::pool::Pool;
+
+/// What the scheduler waits for besides deadlines.
+pub enum Event {
+    /// A plugin instance completed its execution, along with the id of its slot.
+    Done(u64, Box<dyn ExecutablePlugin>),
+
+    /// A plugin instance panicked while executing, along with the id of its slot and its name.
+    Failed(u64, String),
+
+    /// SIGHUP was received, the configuration must be reloaded.
+    Reload,
+
+    /// SIGTERM or SIGINT was received, the program must exit.
+    Shutdown,
+}
+
+/**
+Slot of a plugin instance in the scheduler.
+The instance is taken out of the slot while it is executed by the pool.
+*/
+struct Slot {
+    id: u64,
+    name: String,
+    config: toml::Value,
+    instance: Option<Box<dyn ExecutablePlugin>>,
+    interval: time::Duration,
+    deadline: time::Duration,
+}
+
+/**
+Execute each plugin instance on its own deadline.
+
+Deadlines are aligned on wall-clock multiples of the instance interval, so that
+the execution time does not add up to the period. When an instance takes longer
+than its interval to execute, the deadlines it missed are reported and skipped
+instead of being caught up.
+
+On SIGHUP the plugin sections of the configuration file are loaded again. The
+instances whose configuration did not change are kept along with their state,
+and if the new configuration is not valid the current one is kept altogether.
+The other sections are only applied on restart, changes to them are reported.
+
+On SIGTERM or SIGINT no more instance is executed. The running ones are given
+shutdown_timeout to complete, then the state of the instances is persisted,
+whatever the outputs buffered is sent and the program exits. A second signal
+skips the wait.
+*/
+pub struct Scheduler {
+    slots: Vec<Slot>,
+    next_id: u64,
+    pool: Pool,
+
+    /// Number of instances submitted to the pool and not completed yet.
+    running: usize,
+
+    events: mpsc::Receiver<Event>,
+    output: Arc<dyn Output>,
+    plugins: Plugins,
+    config_path: PathBuf,
+
+    /// Sections of the configuration file that are only applied on restart, see config::RESTART_ONLY.
+    restart_only: toml::Table,
+
+    shutdown_timeout: time::Duration,
+}
+
+impl Scheduler {
+    pub fn new(
+        plugins: Plugins,
+        config_path: PathBuf,
+        instances: Vec<Loaded>,
+        restart_only: toml::Table,
+        concurrency: usize,
+        shutdown_timeout: time::Duration,
+        output: Arc<dyn Output>,
+    ) -> Self {
+        let (events_tx, events_rx) = mpsc::channel::<Event>();
+        watch_signals(events_tx.clone());
+
+        let mut scheduler = Self {
+            slots: vec![],
+            next_id: 0,
+            pool: Pool::new(concurrency, Arc::clone(&output), events_tx),
+            running: 0,
+            events: events_rx,
+            output,
+            plugins,
+            config_path,
+            restart_only,
+            shutdown_timeout,
+        };
+        scheduler.install(instances);
+
+        scheduler
+    }
+
+    /// Run the plugin instances until the program is asked to exit.
+    pub fn run(&mut self) -> ! {
+        loop {
+            let now = plugin::now();
+
+            // submit the instances that are due and not already running
+            let mut next_deadline: Option<time::Duration> = None;
+            for slot in self.slots.iter_mut() {
+                if slot.instance.is_none() {
+                    continue;
+                }
+
+                if slot.deadline <= now {
+                    self.pool.submit(slot.id, slot.instance.take().unwrap());
+                    self.running += 1;
+                } else if next_deadline.is_none_or(|next| slot.deadline < next) {
+                    next_deadline = Some(slot.deadline);
+                }
+            }
+
+            // wait for the next deadline or for something to happen
+            let timeout = next_deadline
+                .map(|next| next.saturating_sub(now))
+                .unwrap_or(time::Duration::MAX);
+            match self.events.recv_timeout(timeout) {
+                Ok(Event::Done(id, instance)) => self.complete(id, instance),
+                Ok(Event::Failed(id, name)) => self.failed(id, &name),
+                Ok(Event::Reload) => self.reload(),
+                Ok(Event::Shutdown) => self.shutdown(),
+                Err(_) => (),
+            }
+        }
+    }
+
+    /**
+    Replace the slots by the loaded instances. Instances that were left
+    unchanged keep their slot, new ones are executed right away.
+    */
+    fn install(&mut self, instances: Vec<Loaded>) {
+        let now = plugin::now();
+        let mut previous = mem::take(&mut self.slots);
+
+        for loaded in instances {
+            let slot = match loaded.instance {
+                Some(instance) => {
+                    self.next_id += 1;
+                    Slot {
+                        id: self.next_id,
+                        name: loaded.name,
+                        config: loaded.config,
+                        interval: instance.interval(),
+                        instance: Some(instance),
+                        deadline: now,
+                    }
+                }
+                None => {
+                    let index = previous
+                        .iter()
+                        .position(|slot| slot.name == loaded.name)
+                        .unwrap();
+                    previous.swap_remove(index)
+                }
+            };
+
+            self.slots.push(slot);
+        }
+
+        // instances of the previous slots that are still executing are dropped once completed
+    }
+
+    /// Load the plugin sections of the configuration file again.
+    fn reload(&mut self) {
+        let mut config = match config::config(&self.config_path) {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!(
+                    "error: cannot reload configuration file '{}', keeping the current one: {}",
+                    self.config_path.display(),
+                    interpolation::redact(&e.to_string())
+                );
+                return;
+            }
+        };
+
+        for key in config::RESTART_ONLY {
+            if config.restart_only.get(*key) != self.restart_only.get(*key) {
+                eprintln!("warning: {} changes are only applied on restart", key);
+            }
+        }
+
+        let slots = &self.slots;
+        let unchanged = |name: &str, config: &toml::Value| {
+            slots
+                .iter()
+                .any(|slot| slot.name == name && slot.config == *config)
+        };
+
+        let mut errors = mem::take(&mut config.errors);
+        let loaded = self
+            .plugins
+            .load(config.plugins, &config.locations, unchanged);
+        match loaded {
+            Ok(instances) if errors.is_empty() => self.install(instances),
+            Ok(_) => self.reload_failed(errors),
+            Err(plugin_errors) => {
+                errors.extend(plugin_errors);
+                self.reload_failed(errors);
+            }
+        }
+    }
+
+    /// Report why the configuration could not be reloaded.
+    fn reload_failed(&self, errors: Vec<String>) {
+        eprintln!(
+            "error: cannot reload configuration file '{}', keeping the current one:",
+            self.config_path.display()
+        );
+        for e in errors {
+            eprintln!("    {}", interpolation::redact(&e));
+        }
+    }
+
+    /// Give an executed instance back to its slot and compute its next deadline.
+    fn complete(&mut self, id: u64, instance: Box<dyn ExecutablePlugin>) {
+        let now = plugin::now();
+        self.running -= 1;
+
+        // the slot is gone if the instance was changed or removed while executing
+        if let Some(slot) = self.slots.iter_mut().find(|slot| slot.id == id) {
+            let mut deadline = next_multiple(slot.deadline, slot.interval);
+            if deadline <= now {
+                let missed = (now - deadline).as_nanos() / slot.interval.as_nanos() + 1;
+                eprintln!(
+                    "warning: '{}' missed {} deadline(s), execution took longer than its interval",
+                    instance.name(),
+                    missed
+                );
+                deadline = next_multiple(now, slot.interval);
+            }
+
+            slot.deadline = deadline;
+            slot.instance = Some(instance);
+        }
+
+        self.output.flush();
+    }
+
+    /**
+    Drop the slot of an instance that panicked, so that it is not executed
+    anymore. Its instance is created again if the configuration is reloaded.
+    */
+    fn failed(&mut self, id: u64, name: &str) {
+        self.running -= 1;
+        self.slots.retain(|slot| slot.id != id);
+        eprintln!(
+            "error: '{}' panicked, it is not executed anymore until the configuration is reloaded",
+            name
+        );
+
+        self.output.flush();
+    }
+
+    /// Wait for the running instances, persist the state of all of them and exit.
+    fn shutdown(&mut self) -> ! {
+        let deadline = time::Instant::now() + self.shutdown_timeout;
+
+        while self.running > 0 {
+            let timeout = deadline.saturating_duration_since(time::Instant::now());
+            match self.events.recv_timeout(timeout) {
+                Ok(Event::Done(id, instance)) => {
+                    // no need for a new deadline, the instance is only kept to be persisted
+                    self.running -= 1;
+                    if let Some(slot) = self.slots.iter_mut().find(|slot| slot.id == id) {
+                        slot.instance = Some(instance);
+                    }
+                }
+                Ok(Event::Failed(_, name)) => {
+                    self.running -= 1;
+                    eprintln!("error: '{}' panicked", name);
+                }
+                Ok(Event::Reload) => (),
+                Ok(Event::Shutdown) | Err(_) => {
+                    eprintln!(
+                        "warning: exiting while {} plugin instance(s) are still running",
+                        self.running
+                    );
+                    break;
+                }
+            }
+        }
+
+        // instances are dropped as well, so that external plugins free their state
+        for slot in self.slots.iter_mut() {
+            if let Some(mut instance) = slot.instance.take() {
+                instance.persist();
+            }
+        }
+
+        self.output.close();
+        exit(0);
+    }
+}
+
+/**
+Execute each plugin instance a single time, with the same pool of workers as
+the scheduler, then persist their state.
+*/
+pub fn once(instances: Vec<Loaded>, concurrency: usize, output: Arc<dyn Output>) {
+    let (events_tx, events_rx) = mpsc::channel::<Event>();
+    let pool = Pool::new(concurrency, Arc::clone(&output), events_tx);
+
+    let mut running = 0;
+    for (id, loaded) in instances.into_iter().enumerate() {
+        if let Some(instance) = loaded.instance {
+            pool.submit(id as u64, instance);
+            running += 1;
+        }
+    }
+
+    while running > 0 {
+        match events_rx.recv() {
+            Ok(Event::Done(_, mut instance)) => {
+                instance.persist();
+                running -= 1;
+            }
+            Ok(Event::Failed(_, name)) => {
+                eprintln!("error: '{}' panicked", name);
+                running -= 1;
+            }
+            Ok(_) => (),
+            Err(_) => break,
+        }
+    }
+
+    output.close();
+}
+
+/// Forward the signals to the scheduler.
+fn watch_signals(events: mpsc::Sender<Event>) {
+    let mut signals = match Signals::new([SIGHUP, SIGTERM, SIGINT]) {
+        Ok(signals) => signals,
+        Err(e) => {
+            eprintln!(
+                "warning: cannot handle signals, configuration reload and graceful shutdown are disabled: {}",
+                e
+            );
+            return;
+        }
+    };
+
+    thread::spawn(move || {
+        for signal in signals.forever() {
+            let event = match signal {
+                SIGHUP => Event::Reload,
+                _ => Event::Shutdown,
+            };
+
+            if events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// Return the first multiple of `interval` since the epoch strictly after `time`.
+fn next_multiple(time: time::Duration, interval: time::Duration) -> time::Duration {
+    let interval_ns = interval.as_nanos().max(1);
+    let next_ns = (time.as_nanos() / interval_ns + 1) * interval_ns;
+
+    time::Duration::new(
+        (next_ns / 1_000_000_000) as u64,
+        (next_ns % 1_000_000_000) as u32,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(secs: f64) -> time::Duration {
+        time::Duration::from_secs_f64(secs)
+    }
+
+    #[test]
+    fn next_multiple_is_strictly_after() {
+        assert_eq!(next_multiple(secs(0.0), secs(10.0)), secs(10.0));
+        assert_eq!(next_multiple(secs(3.0), secs(10.0)), secs(10.0));
+        assert_eq!(next_multiple(secs(10.0), secs(10.0)), secs(20.0));
+        assert_eq!(next_multiple(secs(19.999), secs(10.0)), secs(20.0));
+    }
+
+    #[test]
+    fn next_multiple_handles_sub_second_intervals() {
+        assert_eq!(next_multiple(secs(1.2), secs(0.5)), secs(1.5));
+        assert_eq!(
+            next_multiple(time::Duration::new(1_700_000_000, 999_999_999), secs(0.25)),
+            time::Duration::new(1_700_000_001, 0)
+        );
+    }
+
+    #[test]
+    fn next_multiple_of_a_zero_interval_is_the_next_nanosecond() {
+        assert_eq!(
+            next_multiple(time::Duration::new(5, 7), time::Duration::ZERO),
+            time::Duration::new(5, 8)
+        );
+    }
+}
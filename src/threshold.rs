@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use crate::config::{Limits, ThresholdConfig};
+use crate::output::Severity;
+
+/// Description of a bound crossed by a value, along with the bound itself.
+type Bound = (&'static str, f64);
+
+/// State of a single value with respect to its thresholds.
+#[derive(Debug, Clone)]
+struct ValueState {
+    /// The state that was last notified.
+    current: Severity,
+
+    /// The state the value is switching to and for how many consecutive values.
+    pending: Severity,
+    hits: u32,
+}
+
+/**
+Check the values of a plugin instance against its thresholds.
+It keeps the state of each value, identified by its type instance,
+so that a notification is only emitted when the state changes.
+*/
+#[derive(Debug, Clone)]
+pub struct Thresholds {
+    config: ThresholdConfig,
+    states: HashMap<String, ValueState>,
+}
+
+impl Thresholds {
+    pub fn new(config: ThresholdConfig) -> Self {
+        Self { config, states: HashMap::new() }
+    }
+
+    /**
+    Check a value, made of one value for each data source, and return the new
+    state along with a message if a notification should be emitted. Undefined
+    and non numeric values are ignored. The most severe data source wins.
+    */
+    pub fn check(&mut self, type_instance: &str, values: &[String]) -> Option<(Severity, String)> {
+        let limits = match self
+            .config
+            .targets
+            .as_ref()
+            .and_then(|t| t.get(type_instance))
+        {
+            Some(limits) => limits.or(&self.config.limits),
+            None => self.config.limits.to_owned(),
+        };
+
+        let state = self
+            .states
+            .entry(type_instance.to_owned())
+            .or_insert(ValueState {
+                current: Severity::Okay,
+                pending: Severity::Okay,
+                hits: 0,
+            });
+
+        // the data source that is the furthest from okay, along with the bound it crossed
+        let mut worst: Option<(Severity, usize, f64, Option<Bound>)> = None;
+        for (index, value) in values.iter().enumerate() {
+            let value = match value.parse::<f64>() {
+                Ok(value) if !value.is_nan() => value,
+                _ => continue,
+            };
+
+            let (severity, bound) = evaluate(&limits, value, state.current);
+            if worst.as_ref().is_none_or(|(worst, ..)| severity > *worst) {
+                worst = Some((severity, index, value, bound));
+            }
+        }
+
+        let (severity, index, value, bound) = worst?;
+
+        if severity == state.current {
+            state.hits = 0;
+            return None;
+        }
+
+        if severity == state.pending && state.hits > 0 {
+            state.hits += 1;
+        } else {
+            state.pending = severity;
+            state.hits = 1;
+        }
+
+        if state.hits < limits.persist.unwrap_or(1).max(1) {
+            return None;
+        }
+
+        state.current = severity;
+        state.hits = 0;
+
+        let data_source = if values.len() > 1 {
+            format!(" of data source {}", index)
+        } else {
+            String::new()
+        };
+        let message = match bound {
+            Some((bound, limit)) => {
+                format!("value {}{} is {} of {}", value, data_source, bound, limit)
+            }
+            None => format!("value {}{} is back within thresholds", value, data_source),
+        };
+
+        Some((severity, message))
+    }
+}
+
+/**
+Severity of a value along with the bound that it crossed. The hysteresis
+widens the range of the states the value is already in, so that it must
+come back by that much before leaving them.
+*/
+fn evaluate(limits: &Limits, value: f64, current: Severity) -> (Severity, Option<Bound>) {
+    let hysteresis = limits.hysteresis.unwrap_or(0.0);
+    let margin = |severity| if current >= severity { hysteresis } else { 0.0 };
+
+    let failure = margin(Severity::Failure);
+    if let Some(min) = limits.failure_min.filter(|min| value < min + failure) {
+        return (Severity::Failure, Some(("below the failure minimum", min)));
+    }
+    if let Some(max) = limits.failure_max.filter(|max| value > max - failure) {
+        return (Severity::Failure, Some(("above the failure maximum", max)));
+    }
+
+    let warning = margin(Severity::Warning);
+    if let Some(min) = limits.warning_min.filter(|min| value < min + warning) {
+        return (Severity::Warning, Some(("below the warning minimum", min)));
+    }
+    if let Some(max) = limits.warning_max.filter(|max| value > max - warning) {
+        return (Severity::Warning, Some(("above the warning maximum", max)));
+    }
+
+    (Severity::Okay, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn thresholds(config: &str) -> Thresholds {
+        Thresholds::new(toml::from_str(config).unwrap())
+    }
+
+    fn check(thresholds: &mut Thresholds, value: &str) -> Option<Severity> {
+        thresholds
+            .check("a", &[value.to_owned()])
+            .map(|(severity, _)| severity)
+    }
+
+    #[test]
+    fn evaluate_widens_the_current_states() {
+        let limits: Limits = toml::from_str(
+            "warning_min = 10.0\nwarning_max = 80.0\nfailure_max = 90.0\nhysteresis = 5.0",
+        )
+        .unwrap();
+        let severity = |value, current| evaluate(&limits, value, current).0;
+
+        assert_eq!(severity(50.0, Severity::Okay), Severity::Okay);
+        assert_eq!(severity(9.0, Severity::Okay), Severity::Warning);
+        assert_eq!(severity(85.0, Severity::Okay), Severity::Warning);
+        assert_eq!(severity(78.0, Severity::Warning), Severity::Warning);
+        assert_eq!(severity(74.0, Severity::Warning), Severity::Okay);
+        assert_eq!(severity(87.0, Severity::Warning), Severity::Warning);
+        assert_eq!(severity(87.0, Severity::Failure), Severity::Failure);
+        assert_eq!(severity(84.0, Severity::Failure), Severity::Warning);
+    }
+
+    #[test]
+    fn notifies_state_changes_only() {
+        let mut thresholds = thresholds("warning_max = 80.0");
+
+        assert_eq!(check(&mut thresholds, "50"), None);
+        assert_eq!(check(&mut thresholds, "85"), Some(Severity::Warning));
+        assert_eq!(check(&mut thresholds, "86"), None);
+        assert_eq!(check(&mut thresholds, "U"), None);
+        assert_eq!(check(&mut thresholds, "50"), Some(Severity::Okay));
+    }
+
+    #[test]
+    fn persist_needs_consecutive_values() {
+        let mut thresholds = thresholds("warning_max = 80.0\npersist = 2");
+
+        assert_eq!(check(&mut thresholds, "85"), None);
+        assert_eq!(check(&mut thresholds, "50"), None);
+        assert_eq!(check(&mut thresholds, "85"), None);
+        assert_eq!(check(&mut thresholds, "85"), Some(Severity::Warning));
+        assert_eq!(check(&mut thresholds, "50"), None);
+        assert_eq!(check(&mut thresholds, "85"), None);
+        assert_eq!(check(&mut thresholds, "50"), None);
+        assert_eq!(check(&mut thresholds, "50"), Some(Severity::Okay));
+    }
+
+    #[test]
+    fn hysteresis_delays_the_way_back() {
+        let mut thresholds = thresholds("warning_max = 80.0\nhysteresis = 5.0");
+
+        assert_eq!(check(&mut thresholds, "81"), Some(Severity::Warning));
+        assert_eq!(check(&mut thresholds, "78"), None);
+        assert_eq!(check(&mut thresholds, "74"), Some(Severity::Okay));
+        assert_eq!(check(&mut thresholds, "78"), None);
+    }
+
+    #[test]
+    fn the_worst_data_source_wins() {
+        let mut thresholds =
+            thresholds("warning_max = 80.0\nfailure_max = 90.0\n[targets.b]\nfailure_max = 99.0");
+
+        let values = ["85".to_owned(), "95".to_owned()];
+        let (severity, message) = thresholds.check("a", &values).unwrap();
+        assert_eq!(severity, Severity::Failure);
+        assert_eq!(
+            message,
+            "value 95 of data source 1 is above the failure maximum of 90"
+        );
+
+        let (severity, _) = thresholds.check("b", &values).unwrap();
+        assert_eq!(severity, Severity::Warning);
+    }
+}
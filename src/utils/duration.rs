@@ -0,0 +1,39 @@
+/*!
+Durations given in seconds in the configuration. Unlike Duration::from_secs_f64()
+these functions do not panic on negative, infinite or too large numbers, which
+are reported instead.
+*/
+
+use std::time::Duration;
+
+/// Duration of a number of seconds that may be 0.
+pub fn non_negative(seconds: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Duration of a number of seconds that must be greater than 0.
+pub fn positive(seconds: f64) -> Option<Duration> {
+    non_negative(seconds).filter(|duration| !duration.is_zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_what_cannot_be_converted() {
+        assert_eq!(non_negative(1.5), Some(Duration::from_millis(1500)));
+        assert_eq!(non_negative(0.0), Some(Duration::ZERO));
+        assert_eq!(non_negative(-1.0), None);
+        assert_eq!(non_negative(f64::NAN), None);
+        assert_eq!(non_negative(f64::INFINITY), None);
+        assert_eq!(non_negative(1e30), None);
+    }
+
+    #[test]
+    fn positive_rejects_zero() {
+        assert_eq!(positive(0.0), None);
+        assert_eq!(positive(1e-12), None);
+        assert_eq!(positive(2.0), Some(Duration::from_secs(2)));
+    }
+}
@@ -0,0 +1,61 @@
+/*!
+What the plugins measuring the latency of their targets share. Each target may
+have its own settings, see config::TargetConfig, out of which its query is
+prepared once.
+*/
+
+use crate::config::PluginConfig;
+use crate::plugin::{self, PluginExecImplementation};
+use crate::utils::duration;
+
+/// Query of a target, out of its settings.
+pub trait TargetState<T> {
+    fn new(settings: Option<&T>) -> Self;
+}
+
+/// State of each target, in the order of the targets.
+#[derive(Debug, Clone)]
+pub struct State<S> {
+    targets: Vec<S>,
+}
+
+impl<T, S: TargetState<T>> plugin::State<T> for State<S> {
+    fn new(_instance: &str, conf: &PluginConfig<T>, targets: &[String]) -> Self {
+        let targets = (0..targets.len())
+            .map(|index| S::new(conf.settings_of(index)))
+            .collect();
+
+        Self { targets }
+    }
+}
+
+impl<S> State<S> {
+    /// Each target along with its state.
+    pub fn targets<'a, 'b>(
+        &'b self,
+        targets: &'a [String],
+    ) -> impl Iterator<Item = (&'a String, &'b S)> {
+        targets.iter().zip(&self.targets)
+    }
+}
+
+/// Check the timeout of each target, given by the function out of its settings.
+pub fn check_timeouts<T: PluginExecImplementation>(
+    instance: &str,
+    conf: &PluginConfig<T>,
+    targets: &[String],
+    timeout: impl Fn(&T) -> Option<f32>,
+) -> Result<(), String> {
+    for index in 0..targets.len() {
+        let timeout = conf.settings_of(index).and_then(&timeout);
+        if timeout.is_some_and(|timeout| duration::positive(f64::from(timeout)).is_none()) {
+            return Err(format!(
+                "'{}:{}' timeout must be a positive number of seconds",
+                T::name(),
+                instance
+            ));
+        }
+    }
+
+    Ok(())
+}
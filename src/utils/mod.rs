@@ -1,2 +1,7 @@
+pub mod duration;
+#[cfg(any(feature = "http_latency", feature = "telnet_latency"))]
+pub mod latency;
 #[cfg(feature = "sysctl")]
 pub mod sysctl;
+#[cfg(any(feature = "file", feature = "file_factor"))]
+pub mod wildcard;
@@ -0,0 +1,549 @@
+/*!
+Targets given as glob patterns, such as `/sys/class/hwmon/hwmon?/temp[0-9]_input`,
+for the plugins reading files.
+
+A pattern is expanded to the matching paths when the instance is created, and
+again every `rescan` seconds if configured. The type instance of a matching path
+is the path itself, unless a template is given. In the template `$1`, `$2`...
+are replaced by what matched each wildcard of the pattern (`*`, `?`, `[...]` or
+the directories matched by `**`) and `$0` by the whole path, e.g. "hwmon$1-temp$2".
+A name given to a pattern is its template, instead of the `type_instance` setting.
+Paths that would get the same type instance as another path, matching or not,
+are reported instead of being read. As the number of matching paths varies,
+patterns cannot be combined with `data_sources`.
+*/
+
+use std::collections::HashMap;
+use std::time;
+
+use crate::config::PluginConfig;
+use crate::plugin::{self, PluginError, PluginResult};
+use crate::utils::duration;
+
+/// Settings of the plugins whose targets may be patterns.
+pub trait Settings {
+    /// Template of the paths matching a pattern that has no name.
+    fn type_instance(&self) -> Option<&str>;
+
+    /// Seconds between two expansions of the patterns.
+    fn rescan(&self) -> Option<f64>;
+}
+
+/// Whether a target is a glob pattern rather than a path.
+pub fn is_pattern(target: &str) -> bool {
+    target.contains(['*', '?', '['])
+}
+
+/// Check the patterns found in the targets of an instance, along with their settings.
+pub fn check_config<T: Settings>(conf: &PluginConfig<T>, targets: &[String]) -> Result<(), String> {
+    // the number of paths matching a pattern varies, they cannot be grouped
+    if conf
+        .data_sources
+        .is_some_and(|data_sources| data_sources > 1)
+        && targets.iter().any(|target| is_pattern(target))
+    {
+        return Err("targets cannot be patterns along with data_sources".to_owned());
+    }
+
+    let settings = conf.settings.as_ref();
+    check(
+        targets,
+        &conf.target_names(),
+        settings.and_then(T::type_instance),
+        settings.and_then(T::rescan),
+    )
+}
+
+/// Check the patterns along with their templates, a name or else the type_instance setting.
+fn check(
+    targets: &[String],
+    names: &[Option<&str>],
+    type_instance: Option<&str>,
+    rescan: Option<f64>,
+) -> Result<(), String> {
+    if rescan.is_some_and(|rescan| duration::positive(rescan).is_none()) {
+        return Err("rescan must be a positive number of seconds".to_owned());
+    }
+
+    for (pattern, name) in targets.iter().zip(names) {
+        if !is_pattern(pattern) {
+            continue;
+        }
+
+        glob::Pattern::new(pattern).map_err(|e| format!("invalid pattern '{}': {}", pattern, e))?;
+
+        let wildcards = tokens(pattern)
+            .iter()
+            .filter(|token| !matches!(token, Token::Literal(_)))
+            .count();
+
+        let template = name.or(type_instance).unwrap_or("");
+        for reference in references(template) {
+            if reference > wildcards {
+                return Err(format!(
+                    "'{}' references ${} but '{}' only has {} wildcard(s)",
+                    template, reference, pattern, wildcards
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Configured target, once expanded.
+enum Target {
+    /// Index of a target that is not a pattern.
+    Path(usize),
+
+    /// Path matching a pattern, along with its type instance.
+    Matched { path: String, type_instance: String },
+
+    /// Path matching a pattern that cannot be given a type instance, along with why.
+    Invalid { path: String, message: String },
+
+    /// Index of a pattern that matches nothing.
+    Unmatched(usize),
+}
+
+/// Targets of an instance, with their patterns expanded.
+pub struct Targets {
+    /// Name given to each target if any, the template of a pattern.
+    names: Vec<Option<String>>,
+
+    /// Template of the patterns without a name.
+    type_instance: Option<String>,
+
+    rescan: Option<time::Duration>,
+    scanned: time::Instant,
+    expanded: Vec<Target>,
+}
+
+impl<T: Settings> plugin::State<T> for Targets {
+    fn new(_instance: &str, conf: &PluginConfig<T>, targets: &[String]) -> Self {
+        let settings = conf.settings.as_ref();
+        Targets::new(
+            targets,
+            &conf.target_names(),
+            settings.and_then(T::type_instance),
+            settings.and_then(T::rescan),
+        )
+    }
+}
+
+impl Targets {
+    fn new(
+        targets: &[String],
+        names: &[Option<&str>],
+        type_instance: Option<&str>,
+        rescan: Option<f64>,
+    ) -> Self {
+        let mut expanded = Self {
+            names: names.iter().map(|name| name.map(str::to_owned)).collect(),
+            type_instance: type_instance.map(str::to_owned),
+            rescan: rescan.and_then(duration::positive),
+            scanned: time::Instant::now(),
+            expanded: vec![],
+        };
+        expanded.scan(targets);
+
+        expanded
+    }
+
+    fn scan(&mut self, targets: &[String]) {
+        self.scanned = time::Instant::now();
+        self.expanded.clear();
+
+        // path of each type instance, which must be unique, starting with the plain paths
+        let mut used: HashMap<String, String> = HashMap::new();
+        for (target, name) in targets.iter().zip(&self.names) {
+            if !is_pattern(target) {
+                let type_instance = name.as_deref().unwrap_or(target);
+                used.entry(type_instance.to_owned())
+                    .or_insert_with(|| target.to_owned());
+            }
+        }
+
+        for (index, target) in targets.iter().enumerate() {
+            if !is_pattern(target) {
+                self.expanded.push(Target::Path(index));
+                continue;
+            }
+
+            let paths: Vec<String> = glob::glob(target)
+                .map(|paths| {
+                    paths
+                        .flatten()
+                        .map(|path| path.to_string_lossy().into_owned())
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            if paths.is_empty() {
+                self.expanded.push(Target::Unmatched(index));
+            }
+
+            for path in paths {
+                let template = self.names[index]
+                    .as_deref()
+                    .or(self.type_instance.as_deref());
+                let type_instance = match template {
+                    Some(template) => render(template, target, &path),
+                    None => Ok(path.to_owned()),
+                };
+
+                let expanded = match type_instance {
+                    Ok(type_instance) => match used.get(&type_instance) {
+                        Some(other) => Target::Invalid {
+                            message: format!(
+                                "'{}' has the same type instance '{}' as '{}'",
+                                path, type_instance, other
+                            ),
+                            path,
+                        },
+                        None => {
+                            used.insert(type_instance.to_owned(), path.to_owned());
+                            Target::Matched { path, type_instance }
+                        }
+                    },
+                    Err(message) => Target::Invalid { path, message },
+                };
+                self.expanded.push(expanded);
+            }
+        }
+    }
+
+    /**
+    Read each target with the given function, expanding the patterns again
+    first if it is time to. Paths that are not patterns keep being used as
+    target, while matching paths give their type instance.
+    */
+    pub fn read<'a>(
+        &mut self,
+        targets: &'a [String],
+        read: impl Fn(&str) -> Result<String, String>,
+    ) -> Vec<Result<PluginResult<'a>, PluginError<'a>>> {
+        if self
+            .rescan
+            .is_some_and(|rescan| self.scanned.elapsed() >= rescan)
+        {
+            self.scan(targets);
+        }
+
+        self.expanded
+            .iter()
+            .map(|target| match target {
+                Target::Path(index) => {
+                    let target = &targets[*index];
+                    match read(target) {
+                        Ok(value) => Ok(PluginResult {
+                            time: crate::plugin::now(),
+                            values: vec![value],
+                            target: Some(target),
+                            type_instance: None,
+                        }),
+                        Err(message) => Err(PluginError::new(target, message)),
+                    }
+                }
+                Target::Matched { path, type_instance } => match read(path) {
+                    Ok(value) => Ok(PluginResult {
+                        time: crate::plugin::now(),
+                        values: vec![value],
+                        target: None,
+                        type_instance: Some(type_instance.to_owned()),
+                    }),
+                    Err(message) => Err(PluginError {
+                        target: None,
+                        type_instance: Some(type_instance.to_owned()),
+                        message,
+                    }),
+                },
+                Target::Invalid { path, message } => Err(PluginError {
+                    target: None,
+                    type_instance: Some(path.to_owned()),
+                    message: message.to_owned(),
+                }),
+                Target::Unmatched(index) => Err(PluginError::new(
+                    &targets[*index],
+                    format!("no file matches '{}'", targets[*index]),
+                )),
+            })
+            .collect()
+    }
+}
+
+/// Element of a pattern, wildcards capture what they match.
+enum Token {
+    Literal(char),
+    Any,
+    Recursive,
+    One,
+    Class { chars: Vec<char>, negated: bool },
+}
+
+fn tokens(pattern: &str) -> Vec<Token> {
+    let mut tokens = vec![];
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            // a recursive wildcard is a whole path component, see glob::Pattern
+            '*' if chars.next_if_eq(&'*').is_some() => {
+                chars.next_if_eq(&'/');
+                if !matches!(tokens.last(), Some(Token::Recursive)) {
+                    tokens.push(Token::Recursive);
+                }
+            }
+            '*' => tokens.push(Token::Any),
+            '?' => tokens.push(Token::One),
+            '[' => {
+                let negated = chars.next_if(|c| *c == '!').is_some();
+                let mut class: Vec<char> = chars.next().into_iter().collect();
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    class.push(c);
+                }
+                tokens.push(Token::Class { chars: class, negated });
+            }
+            _ => tokens.push(Token::Literal(c)),
+        }
+    }
+
+    tokens
+}
+
+impl Token {
+    /// Whether a single character matches, for the tokens other than Any and Recursive.
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Literal(literal) => *literal == c,
+            Token::Any | Token::Recursive => true,
+            Token::One => c != '/',
+            Token::Class { chars, negated } => {
+                let mut found = false;
+                let mut index = 0;
+                while index < chars.len() {
+                    if index + 2 < chars.len() && chars[index + 1] == '-' {
+                        found |= (chars[index]..=chars[index + 2]).contains(&c);
+                        index += 3;
+                    } else {
+                        found |= chars[index] == c;
+                        index += 1;
+                    }
+                }
+                found != *negated
+            }
+        }
+    }
+}
+
+/// What each wildcard of the pattern matched in the path, if it matches.
+fn captures(tokens: &[Token], path: &[char], captured: &mut Vec<String>) -> bool {
+    let (token, rest) = match tokens.split_first() {
+        Some(split) => split,
+        None => return path.is_empty(),
+    };
+
+    match token {
+        // a star matches within a path component, shortest match first
+        Token::Any => {
+            let max = path.iter().position(|c| *c == '/').unwrap_or(path.len());
+            for len in 0..=max {
+                captured.push(path[..len].iter().collect());
+                if captures(rest, &path[len..], captured) {
+                    return true;
+                }
+                captured.pop();
+            }
+            false
+        }
+        // a recursive wildcard matches whole directories, possibly none
+        Token::Recursive => {
+            for len in 0..=path.len() {
+                if len > 0 && len < path.len() && path[len - 1] != '/' {
+                    continue;
+                }
+                let matched: String = path[..len].iter().collect();
+                captured.push(matched.trim_end_matches('/').to_owned());
+                if captures(rest, &path[len..], captured) {
+                    return true;
+                }
+                captured.pop();
+            }
+            false
+        }
+        _ => match path.split_first() {
+            Some((c, path)) if token.matches(*c) => {
+                let wildcard = !matches!(token, Token::Literal(_));
+                if wildcard {
+                    captured.push(c.to_string());
+                }
+                if captures(rest, path, captured) {
+                    return true;
+                }
+                if wildcard {
+                    captured.pop();
+                }
+                false
+            }
+            _ => false,
+        },
+    }
+}
+
+/// Numbers of the references found in a template.
+fn references(template: &str) -> Vec<usize> {
+    let mut references = vec![];
+    let mut rest = template;
+
+    while let Some(start) = rest.find('$') {
+        rest = &rest[start + 1..];
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        if let Ok(reference) = rest[..digits].parse() {
+            references.push(reference);
+        }
+        rest = &rest[digits..];
+    }
+
+    references
+}
+
+/// Type instance of a path matching a pattern, out of the template.
+fn render(template: &str, pattern: &str, path: &str) -> Result<String, String> {
+    let path_chars: Vec<char> = path.chars().collect();
+    let mut captured = vec![path.to_owned()];
+    if !captures(&tokens(pattern), &path_chars, &mut captured) {
+        return Err(format!(
+            "cannot tell what the wildcards of '{}' matched in '{}'",
+            pattern, path
+        ));
+    }
+
+    let mut rendered = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('$') {
+        rendered.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        match rest[..digits].parse::<usize>() {
+            Ok(reference) => {
+                rendered.push_str(captured.get(reference).map_or("", String::as_str));
+            }
+            Err(_) => rendered.push('$'),
+        }
+        rest = &rest[digits..];
+    }
+    rendered.push_str(rest);
+
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captured(pattern: &str, path: &str) -> Option<Vec<String>> {
+        let path: Vec<char> = path.chars().collect();
+        let mut captured = vec![];
+        captures(&tokens(pattern), &path, &mut captured).then_some(captured)
+    }
+
+    #[test]
+    fn captures_each_wildcard() {
+        assert_eq!(
+            captured("/hwmon*/temp?_[a-z]", "/hwmon12/temp3_x"),
+            Some(vec!["12".to_owned(), "3".to_owned(), "x".to_owned()])
+        );
+        assert_eq!(captured("/[!0-9]", "/a"), Some(vec!["a".to_owned()]));
+        assert_eq!(captured("/[!0-9]", "/1"), None);
+        assert_eq!(captured("/a*", "/b"), None);
+    }
+
+    #[test]
+    fn star_is_shortest_and_within_a_component() {
+        assert_eq!(
+            captured("/*_*", "/a_b_c"),
+            Some(vec!["a".to_owned(), "b_c".to_owned()])
+        );
+        assert_eq!(captured("/*", "/a/b"), None);
+        assert_eq!(captured("/?", "//"), None);
+    }
+
+    #[test]
+    fn recursive_matches_whole_directories() {
+        assert_eq!(captured("/a/**/x", "/a/x"), Some(vec!["".to_owned()]));
+        assert_eq!(
+            captured("/a/**/x", "/a/b/c/x"),
+            Some(vec!["b/c".to_owned()])
+        );
+        assert_eq!(captured("/a/**/x", "/a/bx"), None);
+        assert_eq!(captured("/a/**", "/a/b/c"), Some(vec!["b/c".to_owned()]));
+        assert_eq!(captured("/a/**/**/x", "/a/b/x"), Some(vec!["b".to_owned()]));
+    }
+
+    #[test]
+    fn renders_references() {
+        let rendered = render(
+            "hwmon$1-temp$2",
+            "/hwmon*/temp*_input",
+            "/hwmon0/temp12_input",
+        );
+        assert_eq!(rendered.as_deref(), Ok("hwmon0-temp12"));
+
+        let rendered = render("$0 costs $ or $x", "/*", "/a");
+        assert_eq!(rendered.as_deref(), Ok("/a costs $ or $x"));
+    }
+
+    #[test]
+    fn render_fails_when_the_path_does_not_match() {
+        assert!(render("$1", "/a/*", "/b/c").is_err());
+    }
+
+    #[test]
+    fn checks_references_against_wildcards() {
+        let targets = vec!["/a/**/*".to_owned(), "/plain".to_owned()];
+        assert!(check(&targets, &[None, None], Some("$2"), None).is_ok());
+        assert!(check(&targets, &[Some("$3"), None], Some("$2"), None).is_err());
+        assert!(check(&targets, &[None, None], None, Some(0.0)).is_err());
+    }
+
+    #[test]
+    fn matches_cannot_take_the_type_instance_of_a_path() {
+        let dir = std::env::temp_dir().join(format!("shrimp-wildcard-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("t1"), "").unwrap();
+        std::fs::write(dir.join("t2"), "").unwrap();
+
+        let targets = vec![
+            dir.join("t1").display().to_string(),
+            dir.join("t*").display().to_string(),
+        ];
+        let mut expanded = Targets::new(&targets, &[Some("x2"), Some("x$1")], None, None);
+        let results = expanded.read(&targets, |_| Ok("1".to_owned()));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let type_instances: Vec<_> = results
+            .iter()
+            .map(|result| match result {
+                Ok(result) => Ok(result.type_instance.as_deref().or(result.target)),
+                Err(error) => Err(error.type_instance.as_deref()),
+            })
+            .collect();
+        assert_eq!(
+            type_instances,
+            vec![
+                Ok(Some(targets[0].as_str())),
+                Ok(Some("x1")),
+                Err(Some(dir.join("t2").display().to_string().as_str())),
+            ]
+        );
+    }
+
+    #[test]
+    fn references_are_numbers_after_dollars() {
+        assert_eq!(references("a$1b$12$x$"), vec![1, 12]);
+    }
+}